@@ -0,0 +1,162 @@
+// A free replacement for the boot ROM, so games can be started from power on without a copy
+// of Nintendo's. It clears VRAM, scrolls the cartridge's logo down the screen, sums the header
+// as the DMG's does (which is where F comes from) and hands over at 0x0100 with the registers as
+// the selected model's boot ROM leaves them. IO such as DIV and STAT can't be set from code, so
+// the emulator sets it to the model's post boot values as the ROM unmaps itself. Unlike the
+// originals it doesn't lock up on a bad logo or checksum.
+//
+// Frames are timed with a delay loop rather than by polling LY, so it runs the same whether or
+// not the LCD is emulated.
+
+use crate::instructions;
+use crate::model::Model;
+
+// Operands patched per model in the hand over code
+const SET_FLAGS: usize = 0xEC;
+const BC: usize = 0xEF;
+const DE: usize = 0xF2;
+const HL: usize = 0xF5;
+const A: usize = 0xFB;
+
+const FREE_BOOT_ROM: [u8; 0x100] = [
+    // Clear VRAM
+    0x31, 0xFE, 0xFF,       // 0000 LD SP,$FFFE
+    0xAF,                   // 0003 XOR A
+    0x21, 0xFF, 0x9F,       // 0004 LD HL,$9FFF
+    0x32,                   // 0007 clear: LD (HL-),A
+    0xCB, 0x7C,             // 0008 BIT 7,H
+    0x20, 0xFB,             // 000A JR NZ,clear
+
+    // Audio and palette registers as the boot ROM leaves them
+    0x3E, 0x80,             // 000C LD A,$80
+    0xE0, 0x26,             // 000E LDH (NR52),A
+    0xE0, 0x11,             // 0010 LDH (NR11),A
+    0x3E, 0xF3,             // 0012 LD A,$F3
+    0xE0, 0x12,             // 0014 LDH (NR12),A
+    0xE0, 0x25,             // 0016 LDH (NR51),A
+    0x3E, 0x77,             // 0018 LD A,$77
+    0xE0, 0x24,             // 001A LDH (NR50),A
+    0x3E, 0xFC,             // 001C LD A,$FC
+    0xE0, 0x47,             // 001E LDH (BGP),A
+
+    // Expand the header logo into tiles 1-24, doubling every pixel
+    0x11, 0x04, 0x01,       // 0020 LD DE,$0104
+    0x21, 0x10, 0x80,       // 0023 LD HL,$8010
+    0x1A,                   // 0026 logo: LD A,(DE)
+    0x13,                   // 0027 INC DE
+    0x4F,                   // 0028 LD C,A
+    0xCD, 0x69, 0x00,       // 0029 CALL expand
+    0xCD, 0x69, 0x00,       // 002C CALL expand
+    0x7B,                   // 002F LD A,E
+    0xFE, 0x34,             // 0030 CP $34
+    0x20, 0xF2,             // 0032 JR NZ,logo
+
+    // Lay the tiles out in two rows of 12
+    0x21, 0x04, 0x99,       // 0034 LD HL,$9904
+    0x3E, 0x01,             // 0037 LD A,$01
+    0xCD, 0x7E, 0x00,       // 0039 CALL row
+    0x2E, 0x24,             // 003C LD L,$24
+    0xCD, 0x7E, 0x00,       // 003E CALL row
+
+    // Switch the LCD on and scroll the logo down into place
+    0x3E, 0x64,             // 0041 LD A,$64
+    0xE0, 0x42,             // 0043 LDH (SCY),A
+    0x3E, 0x91,             // 0045 LD A,$91
+    0xE0, 0x40,             // 0047 LDH (LCDC),A
+    0xCD, 0x86, 0x00,       // 0049 scroll: CALL frame
+    0xF0, 0x42,             // 004C LDH A,(SCY)
+    0x3D,                   // 004E DEC A
+    0xE0, 0x42,             // 004F LDH (SCY),A
+    0x20, 0xF6,             // 0051 JR NZ,scroll
+
+    // Hold it for a second
+    0x16, 0x3C,             // 0053 LD D,$3C
+    0xCD, 0x86, 0x00,       // 0055 hold: CALL frame
+    0x15,                   // 0058 DEC D
+    0x20, 0xFA,             // 0059 JR NZ,hold
+
+    // Sum the header like the DMG boot ROM does, leaving HL at the checksum byte
+    0x21, 0x34, 0x01,       // 005B LD HL,$0134
+    0x06, 0x19,             // 005E LD B,$19
+    0x78,                   // 0060 LD A,B
+    0x86,                   // 0061 sum: ADD (HL)
+    0x23,                   // 0062 INC HL
+    0x05,                   // 0063 DEC B
+    0x20, 0xFB,             // 0064 JR NZ,sum
+    0xC3, 0xEC, 0x00,       // 0066 JP handoff
+
+    // Expand the top nibble of C into two rows of tile data at HL
+    0xD5,                   // 0069 expand: PUSH DE
+    0x06, 0x04,             // 006A LD B,$04
+    0xCB, 0x21,             // 006C bit: SLA C
+    0xF5,                   // 006E PUSH AF
+    0xCB, 0x13,             // 006F RL E
+    0xF1,                   // 0071 POP AF
+    0xCB, 0x13,             // 0072 RL E
+    0x05,                   // 0074 DEC B
+    0x20, 0xF5,             // 0075 JR NZ,bit
+    0x7B,                   // 0077 LD A,E
+    0x22,                   // 0078 LD (HL+),A
+    0x23,                   // 0079 INC HL
+    0x22,                   // 007A LD (HL+),A
+    0x23,                   // 007B INC HL
+    0xD1,                   // 007C POP DE
+    0xC9,                   // 007D RET
+
+    // Write 12 consecutive tile numbers from A to HL
+    0x0E, 0x0C,             // 007E row: LD C,$0C
+    0x22,                   // 0080 tile: LD (HL+),A
+    0x3C,                   // 0081 INC A
+    0x0D,                   // 0082 DEC C
+    0x20, 0xFB,             // 0083 JR NZ,tile
+    0xC9,                   // 0085 RET
+
+    // Wait roughly one frame, 17556 M-cycles
+    0x01, 0xCA, 0x09,       // 0086 frame: LD BC,$09CA
+    0x0B,                   // 0089 wait: DEC BC
+    0x78,                   // 008A LD A,B
+    0xB1,                   // 008B OR C
+    0x20, 0xFB,             // 008C JR NZ,wait
+    0xC9,                   // 008E RET
+
+    // Unused
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+
+    // Hand over, patched per model: set F, then the other registers, then unmap the boot ROM
+    0x86,                   // 00EC handoff: ADD (HL)
+    0x00,                   // 00ED NOP
+    0x01, 0x13, 0x00,       // 00EE LD BC,$0013
+    0x11, 0xD8, 0x00,       // 00F1 LD DE,$00D8
+    0x21, 0x4D, 0x01,       // 00F4 LD HL,$014D
+    0x31, 0xFE, 0xFF,       // 00F7 LD SP,$FFFE
+    0x3E, 0x01,             // 00FA LD A,$01
+    0x00,                   // 00FC NOP
+    0x00,                   // 00FD NOP
+    0xE0, 0x50,             // 00FE LDH (BOOT),A
+];
+
+pub fn free_boot_rom(model: Model) -> Vec<u8> {
+    let mut rom = FREE_BOOT_ROM.to_vec();
+    let state = model.post_boot();
+
+    let set_flags = match state.f {
+        None => [instructions::ADD_A_aHL, instructions::NO_OP],
+        // Z only
+        Some(0x80) => [instructions::XOR_A, instructions::NO_OP],
+        // Every flag clear
+        Some(_) => [instructions::OR_d8, 0xFF],
+    };
+    rom[SET_FLAGS..SET_FLAGS + 2].copy_from_slice(&set_flags);
+    for (offset, value) in [(BC, state.bc), (DE, state.de), (HL, state.hl)] {
+        rom[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+    rom[A] = state.a;
+    rom
+}
@@ -0,0 +1,328 @@
+mod unlicensed;
+mod tpp1;
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+use wasm_bindgen::prelude::*;
+
+use crate::log as console_log;
+
+use unlicensed::{Bbd, BbdVariant, M161, Sachen, SachenVariant, WisdomTree};
+use tpp1::{Tpp1, Tpp1Header};
+
+// The logo every licensed cartridge carries at 0x0104, compared by the boot ROM.
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83,
+    0x00, 0x0C, 0x00, 0x0D, 0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E,
+    0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99, 0xBB, 0xBB, 0x67, 0x63,
+    0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+// Cartridge header, 0x0100-0x014F
+pub struct Header {
+    pub title: String,
+    pub cgb_flag: u8,
+    pub cartridge_type: u8,
+    pub rom_size: u8,
+    pub ram_size: u8,
+    pub header_checksum: u8,
+    // What the boot ROM adds the checksum to: 0x19 plus the bytes 0x0134-0x014C
+    pub header_sum: u8,
+}
+
+impl Header {
+    pub fn parse(rom: &[u8]) -> Self {
+        let byte = |addr: usize| rom.get(addr).copied().unwrap_or(0);
+        let title = (0x0134..0x0144)
+            .map(byte)
+            .take_while(|b| *b != 0)
+            .map(|b| b as char)
+            .collect();
+
+        Self {
+            title,
+            cgb_flag: byte(0x0143),
+            cartridge_type: byte(0x0147),
+            rom_size: byte(0x0148),
+            ram_size: byte(0x0149),
+            header_checksum: byte(0x014D),
+            header_sum: (0x0134..0x014D).map(byte).fold(0x19, u8::wrapping_add),
+        }
+    }
+
+    pub fn has_nintendo_logo(rom: &[u8]) -> bool {
+        rom.get(0x0104..0x0134) == Some(&NINTENDO_LOGO[..])
+    }
+
+    pub fn has_battery(&self) -> bool {
+        matches!(self.cartridge_type, 0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFF)
+    }
+
+    pub fn ram_bytes(&self) -> usize {
+        match self.ram_size {
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            _ => 0,
+        }
+    }
+}
+
+#[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), wasm_bindgen)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MapperKind {
+    RomOnly,
+    WisdomTree,
+    SachenMmc1,
+    SachenMmc2,
+    M161,
+    Bbd,
+    Hitek,
+    Tpp1,
+}
+
+impl MapperKind {
+    // Guess the mapper from the header. Unlicensed carts often carry garbage headers, so for
+    // those we look for the tell-tale signs each one leaves instead. BBD and Hitek carts have
+    // ordinary MBC5 headers and can only be selected by hand. Cartridge types with no mapper
+    // here are an error rather than being run as flat ROM.
+    pub fn detect(rom: &[u8], header: &Header) -> Result<Self, String> {
+        Self::detect_kind(rom, header).ok_or_else(|| {
+            format!("Unsupported cartridge type {:#04x} ({})", header.cartridge_type, Self::type_name(header.cartridge_type))
+        })
+    }
+
+    fn detect_kind(rom: &[u8], header: &Header) -> Option<Self> {
+        if Tpp1Header::parse(rom).is_some() {
+            return Some(Self::Tpp1);
+        }
+
+        if let Some(variant) = Sachen::detect(rom) {
+            return Some(match variant {
+                SachenVariant::Mmc1 => Self::SachenMmc1,
+                SachenVariant::Mmc2 => Self::SachenMmc2,
+            });
+        }
+
+        if WisdomTree::detect(rom, header) {
+            return Some(Self::WisdomTree);
+        }
+
+        if M161::detect(header) {
+            return Some(Self::M161);
+        }
+
+        match header.cartridge_type {
+            0x00 | 0x08 | 0x09 => Some(Self::RomOnly),
+            _ => None,
+        }
+    }
+
+    // What a 0x0147 cartridge type needs, for error messages
+    fn type_name(cartridge_type: u8) -> &'static str {
+        match cartridge_type {
+            0x01..=0x03 => "MBC1",
+            0x05 | 0x06 => "MBC2",
+            0x0B..=0x0D => "MMM01",
+            0x0F..=0x13 => "MBC3",
+            0x19..=0x1E => "MBC5",
+            0x20 => "MBC6",
+            0x22 => "MBC7",
+            0xFC => "Pocket Camera",
+            0xFD => "TAMA5",
+            0xFE => "HuC3",
+            0xFF => "HuC1",
+            _ => "unknown",
+        }
+    }
+}
+
+pub trait Mapper {
+    // Read from the 0x0000-0x7FFF ROM area
+    fn read_rom(&mut self, rom: &[u8], addr: u16) -> u8;
+
+    // Writes to the ROM area drive the mapper registers
+    fn write_rom(&mut self, addr: u16, value: u8);
+
+    // Offset into cartridge RAM for an address in 0xA000-0xBFFF, None if RAM is disabled
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        Some((addr - 0xA000) as usize)
+    }
+
+    // Called for every address the CPU puts on the bus, cartridge or not
+    fn bus_access(&mut self, _addr: u16) {}
+
+    // Put the mapper into the state the boot ROM leaves it in
+    fn post_boot(&mut self) {}
+
+    // Mappers which put registers (such as a clock) in the RAM area handle them here. A write
+    // returns true if it was consumed and shouldn't reach RAM.
+    fn read_register(&mut self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    fn write_register(&mut self, _addr: u16, _value: u8) -> bool {
+        false
+    }
+
+    // Advance any timekeeping hardware on the cartridge
+    fn tick(&mut self, _m_cycles: u64) {}
+
+    // Rumble motor speed, 0 (off) to 3
+    fn rumble(&self) -> u8 {
+        0
+    }
+}
+
+// Offset of a byte within a (wrapped) bank of ROM
+pub fn bank_offset(rom: &[u8], bank: usize, bank_size: usize, addr: usize) -> usize {
+    let banks = (rom.len() / bank_size).max(1);
+    (bank % banks) * bank_size + (addr % bank_size)
+}
+
+pub fn read_rom_byte(rom: &[u8], offset: usize) -> u8 {
+    rom.get(offset).copied().unwrap_or(0xFF)
+}
+
+struct RomOnly;
+
+impl Mapper for RomOnly {
+    fn read_rom(&mut self, rom: &[u8], addr: u16) -> u8 {
+        read_rom_byte(rom, addr as usize)
+    }
+
+    fn write_rom(&mut self, _addr: u16, _value: u8) {}
+}
+
+pub struct Cartridge {
+    pub header: Header,
+    pub kind: MapperKind,
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    battery: bool,
+    // Set when RAM changes, cleared when it is exported
+    ram_dirty: bool,
+    mapper: Box<dyn Mapper>,
+}
+
+impl Cartridge {
+    // Fails for cartridge types without a mapper here, see with_mapper to force one
+    pub fn new(rom: Vec<u8>) -> Result<Self, String> {
+        let header = Header::parse(&rom);
+        let kind = MapperKind::detect(&rom, &header)?;
+        Self::build(rom, header, kind)
+    }
+
+    // Fails only if the ROM can't work with the mapper at all, such as TPP1 without its magic
+    pub fn with_mapper(rom: Vec<u8>, kind: MapperKind) -> Result<Self, String> {
+        let header = Header::parse(&rom);
+        Self::build(rom, header, kind)
+    }
+
+    fn build(rom: Vec<u8>, header: Header, kind: MapperKind) -> Result<Self, String> {
+        console_log(format!("Cartridge \"{}\" using {kind:?} mapper", header.title).as_str());
+
+        let mut ram_bytes = header.ram_bytes();
+        let mut battery = header.has_battery();
+        let mapper: Box<dyn Mapper> = match kind {
+            MapperKind::RomOnly => Box::new(RomOnly),
+            MapperKind::WisdomTree => Box::new(WisdomTree::new()),
+            MapperKind::SachenMmc1 => Box::new(Sachen::new(SachenVariant::Mmc1)),
+            MapperKind::SachenMmc2 => Box::new(Sachen::new(SachenVariant::Mmc2)),
+            MapperKind::M161 => Box::new(M161::new()),
+            MapperKind::Bbd => Box::new(Bbd::new(BbdVariant::Bbd)),
+            MapperKind::Hitek => Box::new(Bbd::new(BbdVariant::Hitek)),
+            MapperKind::Tpp1 => match Tpp1Header::parse(&rom) {
+                Some(tpp1_header) => {
+                    ram_bytes = tpp1_header.ram_bytes();
+                    battery = tpp1_header.has_battery();
+                    Box::new(Tpp1::new(&rom, &tpp1_header))
+                }
+                None => return Err("TPP1 mapper selected but the header has no TPP1 magic".to_string()),
+            },
+        };
+
+        let ram = vec![0u8; ram_bytes];
+
+        Ok(Self { header, kind, rom, ram, battery, ram_dirty: false, mapper })
+    }
+
+    pub fn read8(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x7FFF => self.mapper.read_rom(&self.rom, addr),
+            0xA000..=0xBFFF => {
+                if let Some(value) = self.mapper.read_register(addr) {
+                    return value;
+                }
+                match self.mapper.ram_offset(addr) {
+                    Some(offset) if offset < self.ram.len() => self.ram[offset],
+                    _ => 0xFF,
+                }
+            }
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write8(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x7FFF => self.mapper.write_rom(addr, value),
+            0xA000..=0xBFFF => {
+                if self.mapper.write_register(addr, value) {
+                    return;
+                }
+                if let Some(offset) = self.mapper.ram_offset(addr) {
+                    if offset < self.ram.len() && self.ram[offset] != value {
+                        self.ram[offset] = value;
+                        self.ram_dirty = true;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    pub fn bus_access(&mut self, addr: u16) {
+        self.mapper.bus_access(addr);
+    }
+
+    pub fn post_boot(&mut self) {
+        self.mapper.post_boot();
+    }
+
+    pub fn tick(&mut self, m_cycles: u64) {
+        self.mapper.tick(m_cycles);
+    }
+
+    pub fn rumble(&self) -> u8 {
+        self.mapper.rumble()
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    // Raw contents of cartridge RAM, the same layout as a .sav file
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    pub fn load_ram(&mut self, data: &[u8]) {
+        if data.len() != self.ram.len() {
+            console_log(format!("Save is {} bytes but the cartridge has {} bytes of RAM", data.len(), self.ram.len()).as_str());
+        }
+        let n = data.len().min(self.ram.len());
+        self.ram[..n].copy_from_slice(&data[..n]);
+        self.ram_dirty = false;
+    }
+
+    pub fn ram_dirty(&self) -> bool {
+        self.ram_dirty
+    }
+
+    pub fn clear_ram_dirty(&mut self) {
+        self.ram_dirty = false;
+    }
+}
@@ -0,0 +1,370 @@
+// TPP1, a homebrew mapper for large projects
+// https://github.com/TwitchPlaysPokemon/tpp1/blob/master/tpp1.md
+//
+// Invalid uses of the mapper are logged, since the point of supporting it is to let homebrew
+// developers check their code against the spec.
+
+use super::{bank_offset, read_rom_byte, Mapper, RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+use crate::log as console_log;
+
+pub const MAGIC: [u8; 3] = [0xBC, 0xC1, 0x65];
+
+const FEATURE_RUMBLE: u8 = 0b0001;
+const FEATURE_MULTI_RUMBLE: u8 = 0b0010;
+const FEATURE_RTC: u8 = 0b0100;
+const FEATURE_BATTERY: u8 = 0b1000;
+
+// M-cycles in one RTC second
+const CYCLES_PER_SECOND: u64 = 1 << 20;
+
+pub struct Tpp1Header {
+    pub version: (u8, u8),
+    pub ram_size: u8,
+    pub features: u8,
+}
+
+impl Tpp1Header {
+    pub fn parse(rom: &[u8]) -> Option<Self> {
+        let byte = |addr: usize| rom.get(addr).copied().unwrap_or(0);
+        if [byte(0x0147), byte(0x0149), byte(0x014A)] != MAGIC {
+            return None;
+        }
+
+        Some(Self {
+            version: (byte(0x0150), byte(0x0151)),
+            ram_size: byte(0x0152),
+            features: byte(0x0153),
+        })
+    }
+
+    pub fn ram_bytes(&self) -> usize {
+        match self.ram_size {
+            0 => 0,
+            n => RAM_BANK_SIZE << (n - 1).min(8),
+        }
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.features & FEATURE_BATTERY != 0
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Mapping {
+    Registers,
+    RamReadOnly,
+    RamReadWrite,
+    Rtc,
+    Nothing,
+}
+
+#[derive(Copy, Clone, Default)]
+struct Clock {
+    week: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    second: u8,
+}
+
+impl Clock {
+    // Returns true when the week counter overflows
+    fn advance_second(&mut self) -> bool {
+        self.second += 1;
+        if self.second < 60 { return false; }
+        self.second = 0;
+        self.minute += 1;
+        if self.minute < 60 { return false; }
+        self.minute = 0;
+        self.hour += 1;
+        if self.hour < 24 { return false; }
+        self.hour = 0;
+        self.day += 1;
+        if self.day < 7 { return false; }
+        self.day = 0;
+        let (week, overflow) = self.week.overflowing_add(1);
+        self.week = week;
+        overflow
+    }
+
+    fn get8(&self, index: u16) -> u8 {
+        match index {
+            0 => self.week,
+            1 => (self.day << 5) | self.hour,
+            2 => self.minute,
+            _ => self.second,
+        }
+    }
+
+    fn set8(&mut self, index: u16, value: u8) {
+        match index {
+            0 => self.week = value,
+            1 => {
+                self.day = value >> 5;
+                self.hour = value & 0x1F;
+                if self.day > 6 || self.hour > 23 {
+                    console_log(format!("TPP1: invalid RTC day/hour written: {value:#04x}").as_str());
+                }
+            }
+            2 => {
+                self.minute = value;
+                if value > 59 {
+                    console_log(format!("TPP1: invalid RTC minute written: {value}").as_str());
+                }
+            }
+            _ => {
+                self.second = value;
+                if value > 59 {
+                    console_log(format!("TPP1: invalid RTC second written: {value}").as_str());
+                }
+            }
+        }
+    }
+}
+
+pub struct Tpp1 {
+    features: u8,
+    rom_banks: usize,
+    ram_banks: usize,
+    rom_bank: u16,
+    ram_bank: u8,
+    mapping: Mapping,
+    rumble: u8,
+    clock: Clock,
+    latched: Clock,
+    rtc_running: bool,
+    rtc_overflow: bool,
+    rtc_cycles: u64,
+}
+
+impl Tpp1 {
+    pub fn new(rom: &[u8], header: &Tpp1Header) -> Self {
+        if header.version.0 != 1 {
+            console_log(format!("TPP1: unsupported version {}.{}", header.version.0, header.version.1).as_str());
+        }
+        if header.features & FEATURE_MULTI_RUMBLE != 0 && header.features & FEATURE_RUMBLE == 0 {
+            console_log("TPP1: multiple rumble speeds declared without rumble");
+        }
+
+        Self {
+            features: header.features,
+            rom_banks: (rom.len() / ROM_BANK_SIZE).max(1),
+            ram_banks: header.ram_bytes() / RAM_BANK_SIZE,
+            rom_bank: 1,
+            ram_bank: 0,
+            mapping: Mapping::Registers,
+            rumble: 0,
+            clock: Clock::default(),
+            latched: Clock::default(),
+            rtc_running: false,
+            rtc_overflow: false,
+            rtc_cycles: 0,
+        }
+    }
+
+    fn has(&self, feature: u8) -> bool {
+        self.features & feature != 0
+    }
+
+    fn require(&self, feature: u8, what: &str) -> bool {
+        if !self.has(feature) {
+            console_log(format!("TPP1: {what} used but not declared in the header").as_str());
+        }
+        self.has(feature)
+    }
+
+    fn control(&mut self, command: u8) {
+        match command {
+            0x00 => self.mapping = Mapping::Registers,
+            0x02 | 0x03 => {
+                if self.ram_banks == 0 {
+                    console_log("TPP1: SRAM mapped but the cartridge has none");
+                }
+                self.mapping = if command == 0x02 { Mapping::RamReadOnly } else { Mapping::RamReadWrite };
+            }
+            0x05 => {
+                self.mapping = if self.require(FEATURE_RTC, "RTC") { Mapping::Rtc } else { Mapping::Nothing };
+            }
+            0x10 => {
+                if self.require(FEATURE_RTC, "RTC latch") {
+                    self.latched = self.clock;
+                }
+            }
+            0x11 => {
+                if self.require(FEATURE_RTC, "RTC set") {
+                    self.clock = self.latched;
+                    self.rtc_cycles = 0;
+                }
+            }
+            0x14 => self.rtc_overflow = false,
+            0x18 => self.rtc_running = false,
+            0x19 => self.rtc_running = self.require(FEATURE_RTC, "RTC start"),
+            0x20..=0x23 => {
+                let speed = command & 0x03;
+                if speed == 0 || self.require(FEATURE_RUMBLE, "rumble") {
+                    self.rumble = if speed != 0 && !self.has(FEATURE_MULTI_RUMBLE) {
+                        if speed != 3 {
+                            console_log("TPP1: rumble speed set without multiple rumble speeds, using full speed");
+                        }
+                        3
+                    }
+                    else {
+                        speed
+                    };
+                }
+            }
+            other => console_log(format!("TPP1: invalid MR3 command {other:#04x}").as_str()),
+        }
+    }
+
+    fn status(&self) -> u8 {
+        self.rumble | ((self.rtc_running as u8) << 2) | ((self.rtc_overflow as u8) << 3)
+    }
+}
+
+impl Mapper for Tpp1 {
+    fn read_rom(&mut self, rom: &[u8], addr: u16) -> u8 {
+        let bank = if addr < 0x4000 { 0 } else { self.rom_bank as usize };
+        read_rom_byte(rom, bank_offset(rom, bank, ROM_BANK_SIZE, addr as usize))
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        if addr >= 0x4000 {
+            return;
+        }
+
+        match addr & 0x03 {
+            0 => self.rom_bank = (self.rom_bank & 0xFF00) | value as u16,
+            1 => self.rom_bank = (self.rom_bank & 0x00FF) | ((value as u16) << 8),
+            2 => {
+                self.ram_bank = value;
+                if value as usize >= self.ram_banks.max(1) {
+                    console_log(format!("TPP1: SRAM bank {value} out of range").as_str());
+                }
+            }
+            _ => self.control(value),
+        }
+
+        if addr & 0x03 < 2 && self.rom_bank as usize >= self.rom_banks {
+            console_log(format!("TPP1: ROM bank {} out of range", self.rom_bank).as_str());
+        }
+    }
+
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        match self.mapping {
+            Mapping::RamReadOnly | Mapping::RamReadWrite if self.ram_banks > 0 => {
+                Some((self.ram_bank as usize % self.ram_banks) * RAM_BANK_SIZE + (addr - 0xA000) as usize)
+            }
+            _ => None,
+        }
+    }
+
+    fn read_register(&mut self, addr: u16) -> Option<u8> {
+        let index = addr & 0x03;
+        match self.mapping {
+            Mapping::Registers => Some(match index {
+                0 => self.rom_bank as u8,
+                1 => (self.rom_bank >> 8) as u8,
+                2 => self.ram_bank,
+                _ => self.status(),
+            }),
+            Mapping::Rtc => Some(self.latched.get8(index)),
+            Mapping::Nothing => Some(0xFF),
+            _ => None,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) -> bool {
+        match self.mapping {
+            Mapping::Rtc => {
+                self.latched.set8(addr & 0x03, value);
+                true
+            }
+            Mapping::RamReadOnly => {
+                console_log(format!("TPP1: write to read-only SRAM at {addr:#06x}").as_str());
+                true
+            }
+            Mapping::Registers | Mapping::Nothing => {
+                console_log(format!("TPP1: write to {addr:#06x} while SRAM is not mapped").as_str());
+                true
+            }
+            Mapping::RamReadWrite => false,
+        }
+    }
+
+    fn tick(&mut self, m_cycles: u64) {
+        if !self.rtc_running {
+            return;
+        }
+
+        self.rtc_cycles += m_cycles;
+        while self.rtc_cycles >= CYCLES_PER_SECOND {
+            self.rtc_cycles -= CYCLES_PER_SECOND;
+            if self.clock.advance_second() {
+                self.rtc_overflow = true;
+            }
+        }
+    }
+
+    fn rumble(&self) -> u8 {
+        self.rumble
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{Cartridge, MapperKind};
+
+    fn tpp1_rom(features: u8) -> Vec<u8> {
+        let mut rom: Vec<u8> = (0..4).flat_map(|b| vec![b as u8; ROM_BANK_SIZE]).collect();
+        rom[0x0147] = MAGIC[0];
+        rom[0x0149] = MAGIC[1];
+        rom[0x014A] = MAGIC[2];
+        rom[0x0150] = 1;
+        rom[0x0151] = 0;
+        rom[0x0152] = 1;
+        rom[0x0153] = features;
+        rom
+    }
+
+    #[test]
+    fn detects_and_banks() {
+        let mut cart = Cartridge::new(tpp1_rom(0)).unwrap();
+        assert_eq!(cart.kind, MapperKind::Tpp1);
+        cart.write8(0x0000, 3);
+        assert_eq!(cart.read8(0x4000), 3);
+
+        // Registers are mapped at power on
+        assert_eq!(cart.read8(0xA000), 3);
+
+        cart.write8(0x0003, 0x03);
+        cart.write8(0xA000, 0x42);
+        assert_eq!(cart.read8(0xA000), 0x42);
+        cart.write8(0x0003, 0x02);
+        cart.write8(0xA000, 0x24);
+        assert_eq!(cart.read8(0xA000), 0x42);
+    }
+
+    #[test]
+    fn rtc_counts_and_latches() {
+        let mut cart = Cartridge::new(tpp1_rom(FEATURE_RTC)).unwrap();
+        cart.write8(0x0003, 0x19);
+        cart.tick(CYCLES_PER_SECOND * 61);
+        cart.write8(0x0003, 0x10);
+        cart.write8(0x0003, 0x05);
+        assert_eq!(cart.read8(0xA002), 1);
+        assert_eq!(cart.read8(0xA003), 1);
+    }
+
+    #[test]
+    fn single_speed_rumble_runs_at_full_speed() {
+        let mut cart = Cartridge::new(tpp1_rom(FEATURE_RUMBLE)).unwrap();
+        cart.write8(0x0003, 0x21);
+        assert_eq!(cart.rumble(), 3);
+        cart.write8(0x0003, 0x20);
+        assert_eq!(cart.rumble(), 0);
+    }
+}
@@ -0,0 +1,411 @@
+// Mappers used by unlicensed and pirate cartridges
+// https://gbdev.io/pandocs/Unlicensed_mappers.html (and mGBA's implementations for BBD/Hitek)
+
+use super::{bank_offset, read_rom_byte, Header, Mapper, NINTENDO_LOGO, RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+const WISDOM_TREE_BANK_SIZE: usize = 0x8000;
+
+// Wisdom Tree: the whole 32KiB address space is switched at once, and the bank number comes
+// from the low byte of the *address* written to in 0x0000-0x3FFF. The value is ignored.
+pub struct WisdomTree {
+    bank: usize,
+}
+
+impl WisdomTree {
+    pub fn new() -> Self {
+        Self { bank: 0 }
+    }
+
+    // Wisdom Tree carts claim to be plain ROM carts but are bigger than 32KiB, and all carry
+    // the company name somewhere in the image.
+    pub fn detect(rom: &[u8], header: &Header) -> bool {
+        header.cartridge_type == 0x00
+            && rom.len() > WISDOM_TREE_BANK_SIZE
+            && (contains(rom, b"WISDOM TREE") || contains(rom, b"WISDOM\x00TREE"))
+    }
+}
+
+impl Mapper for WisdomTree {
+    fn read_rom(&mut self, rom: &[u8], addr: u16) -> u8 {
+        read_rom_byte(rom, bank_offset(rom, self.bank, WISDOM_TREE_BANK_SIZE, addr as usize))
+    }
+
+    fn write_rom(&mut self, addr: u16, _value: u8) {
+        if addr < 0x4000 {
+            self.bank = (addr & 0xFF) as usize;
+        }
+    }
+
+    fn ram_offset(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+}
+
+// M161 (Mani 4-in-1): a single write anywhere in the ROM area selects a 32KiB bank, after
+// which the register latches until the console is reset.
+pub struct M161 {
+    bank: usize,
+    latched: bool,
+}
+
+impl M161 {
+    pub fn new() -> Self {
+        Self { bank: 0, latched: false }
+    }
+
+    pub fn detect(header: &Header) -> bool {
+        header.cartridge_type == 0x10 && header.title.starts_with("TETRIS SET")
+    }
+}
+
+impl Mapper for M161 {
+    fn read_rom(&mut self, rom: &[u8], addr: u16) -> u8 {
+        read_rom_byte(rom, bank_offset(rom, self.bank, 0x8000, addr as usize))
+    }
+
+    fn write_rom(&mut self, _addr: u16, value: u8) {
+        if !self.latched {
+            self.bank = (value & 0x07) as usize;
+            self.latched = true;
+        }
+    }
+
+    fn ram_offset(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SachenVariant {
+    Mmc1,
+    Mmc2,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum SachenLock {
+    Locked,
+    // MMC2 only: unlocked once for the DMG logo check, then locked again for the CGB one
+    LockedCgb,
+    Unlocked,
+}
+
+// Rising edges of A15 needed to pass each lock stage
+const SACHEN_UNLOCK_EDGES: u32 = 0x31;
+
+// Sachen MMC1/MMC2: MBC1-like banking with a base/mask pair, plus a "logo lock". While locked,
+// reads from 0x0100-0x01FF have A7 forced high and A0/A6, A1/A4 swapped, so the boot ROM sees
+// a scrambled Nintendo logo stored at 0x0184 while the one at 0x0104 is Sachen's own.
+pub struct Sachen {
+    variant: SachenVariant,
+    lock: SachenLock,
+    a15: bool,
+    edges: u32,
+    base: u8,
+    mask: u8,
+    bank: u8,
+}
+
+impl Sachen {
+    pub fn new(variant: SachenVariant) -> Self {
+        Self {
+            variant,
+            lock: SachenLock::Locked,
+            a15: false,
+            edges: 0,
+            base: 0,
+            mask: 0,
+            bank: 1,
+        }
+    }
+
+    pub fn scramble(addr: u16) -> u16 {
+        let addr = addr | 0x0080;
+        (addr & !0x0053)
+            | ((addr & 0x0001) << 6)
+            | ((addr & 0x0040) >> 6)
+            | ((addr & 0x0002) << 3)
+            | ((addr & 0x0010) >> 3)
+    }
+
+    pub fn detect(rom: &[u8]) -> Option<SachenVariant> {
+        if Header::has_nintendo_logo(rom) {
+            return None;
+        }
+
+        let scrambled_read = |addr: u16| read_rom_byte(rom, Self::scramble(addr) as usize);
+        let logo_matches = (0..NINTENDO_LOGO.len())
+            .all(|i| scrambled_read(0x0104 + i as u16) == NINTENDO_LOGO[i]);
+
+        if !logo_matches {
+            return None;
+        }
+
+        // MMC2 is the CGB-compatible variant
+        if scrambled_read(0x0143) & 0x80 != 0 {
+            Some(SachenVariant::Mmc2)
+        }
+        else {
+            Some(SachenVariant::Mmc1)
+        }
+    }
+
+    fn registers_writable(&self) -> bool {
+        self.bank & 0x30 == 0x30
+    }
+}
+
+impl Mapper for Sachen {
+    fn read_rom(&mut self, rom: &[u8], addr: u16) -> u8 {
+        let addr = match (self.lock, addr) {
+            (SachenLock::Unlocked, _) => addr,
+            (_, 0x0100..=0x01FF) => Self::scramble(addr),
+            _ => addr,
+        };
+
+        let bank = if addr < 0x4000 {
+            self.base & self.mask
+        }
+        else {
+            (self.base & self.mask) | (self.bank & !self.mask)
+        };
+
+        read_rom_byte(rom, bank_offset(rom, bank as usize, ROM_BANK_SIZE, addr as usize))
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF if self.registers_writable() => {
+                self.base = value;
+            }
+            0x2000..=0x3FFF => {
+                self.bank = if value == 0 { 1 } else { value };
+            }
+            0x4000..=0x5FFF if self.registers_writable() => {
+                self.mask = value;
+            }
+            _ => (),
+        }
+    }
+
+    fn ram_offset(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+
+    fn bus_access(&mut self, addr: u16) {
+        let a15 = addr & 0x8000 != 0;
+        let rising = a15 && !self.a15;
+        self.a15 = a15;
+
+        if !rising || self.lock == SachenLock::Unlocked {
+            return;
+        }
+
+        self.edges += 1;
+        if self.edges >= SACHEN_UNLOCK_EDGES {
+            self.edges = 0;
+            self.lock = match (self.variant, self.lock) {
+                (SachenVariant::Mmc2, SachenLock::Locked) => SachenLock::LockedCgb,
+                _ => SachenLock::Unlocked,
+            };
+        }
+    }
+
+    fn post_boot(&mut self) {
+        self.lock = SachenLock::Unlocked;
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BbdVariant {
+    Bbd,
+    Hitek,
+}
+
+// Bit orders indexed by scrambling mode. Entry i gives the source bit for output bit i.
+const BBD_DATA_ORDER: [[u8; 8]; 8] = [
+    [0, 1, 2, 3, 4, 5, 6, 7],
+    [0, 1, 2, 3, 4, 5, 6, 7],
+    [0, 1, 2, 3, 4, 5, 6, 7],
+    [0, 1, 2, 3, 4, 5, 6, 7],
+    [0, 5, 1, 3, 4, 2, 6, 7],
+    [0, 4, 2, 3, 1, 5, 6, 7],
+    [0, 1, 2, 3, 4, 5, 6, 7],
+    [0, 1, 5, 3, 4, 2, 6, 7],
+];
+
+const BBD_BANK_ORDER: [[u8; 8]; 8] = [
+    [0, 1, 2, 3, 4, 5, 6, 7],
+    [3, 4, 2, 0, 1, 5, 6, 7],
+    [0, 1, 2, 3, 4, 5, 6, 7],
+    [3, 2, 1, 0, 4, 5, 6, 7],
+    [0, 1, 2, 3, 4, 5, 6, 7],
+    [1, 2, 3, 4, 0, 5, 6, 7],
+    [0, 1, 2, 3, 4, 5, 6, 7],
+    [0, 1, 2, 3, 4, 5, 6, 7],
+];
+
+const HITEK_DATA_ORDER: [[u8; 8]; 8] = [
+    [7, 6, 5, 4, 3, 2, 1, 0],
+    [0, 1, 2, 3, 4, 5, 6, 7],
+    [7, 1, 2, 3, 4, 5, 6, 0],
+    [6, 1, 2, 3, 4, 5, 0, 7],
+    [5, 1, 2, 3, 4, 0, 6, 7],
+    [4, 1, 2, 3, 0, 5, 6, 7],
+    [3, 1, 2, 0, 4, 5, 6, 7],
+    [2, 1, 0, 3, 4, 5, 6, 7],
+];
+
+const HITEK_BANK_ORDER: [[u8; 8]; 8] = [
+    [0, 1, 2, 3, 4, 5, 6, 7],
+    [3, 2, 1, 0, 4, 5, 7, 6],
+    [1, 0, 3, 2, 4, 5, 6, 7],
+    [2, 3, 0, 1, 4, 5, 6, 7],
+    [0, 1, 2, 3, 4, 5, 6, 7],
+    [2, 1, 0, 3, 4, 5, 6, 7],
+    [1, 2, 3, 0, 4, 5, 6, 7],
+    [0, 1, 3, 2, 4, 5, 6, 7],
+];
+
+fn reorder_bits(value: u8, order: &[u8; 8]) -> u8 {
+    order
+        .iter()
+        .enumerate()
+        .fold(0, |acc, (i, src)| acc | (((value >> src) & 1) << i))
+}
+
+// BBD and Hitek: MBC5 clones which scramble the bits of the ROM bank number and of the data
+// read from the switchable bank. The scrambling modes are set through writes to 0x2080 and
+// 0x2001 respectively.
+pub struct Bbd {
+    data_order: &'static [[u8; 8]; 8],
+    bank_order: &'static [[u8; 8]; 8],
+    data_mode: usize,
+    bank_mode: usize,
+    rom_bank: u16,
+    ram_bank: u8,
+    ram_enabled: bool,
+}
+
+impl Bbd {
+    pub fn new(variant: BbdVariant) -> Self {
+        let (data_order, bank_order) = match variant {
+            BbdVariant::Bbd => (&BBD_DATA_ORDER, &BBD_BANK_ORDER),
+            BbdVariant::Hitek => (&HITEK_DATA_ORDER, &HITEK_BANK_ORDER),
+        };
+
+        Self {
+            data_order,
+            bank_order,
+            data_mode: 0,
+            bank_mode: 0,
+            rom_bank: 1,
+            ram_bank: 0,
+            ram_enabled: false,
+        }
+    }
+}
+
+impl Mapper for Bbd {
+    fn read_rom(&mut self, rom: &[u8], addr: u16) -> u8 {
+        if addr < 0x4000 {
+            return read_rom_byte(rom, addr as usize);
+        }
+
+        let value = read_rom_byte(rom, bank_offset(rom, self.rom_bank as usize, ROM_BANK_SIZE, addr as usize));
+        reorder_bits(value, &self.data_order[self.data_mode])
+    }
+
+    fn write_rom(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => {
+                self.ram_enabled = value & 0x0F == 0x0A;
+            }
+            0x2001 => {
+                self.data_mode = (value & 0x07) as usize;
+            }
+            0x2080 => {
+                self.bank_mode = (value & 0x07) as usize;
+            }
+            0x2000..=0x2FFF => {
+                let bank = reorder_bits(value, &self.bank_order[self.bank_mode]);
+                self.rom_bank = (self.rom_bank & 0x100) | bank as u16;
+            }
+            0x3000..=0x3FFF => {
+                self.rom_bank = (self.rom_bank & 0xFF) | (((value & 0x01) as u16) << 8);
+            }
+            0x4000..=0x5FFF => {
+                self.ram_bank = value & 0x0F;
+            }
+            _ => (),
+        }
+    }
+
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        if self.ram_enabled {
+            Some(self.ram_bank as usize * RAM_BANK_SIZE + (addr - 0xA000) as usize)
+        }
+        else {
+            None
+        }
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{Cartridge, MapperKind};
+
+    fn banked_rom(bank_size: usize, banks: usize) -> Vec<u8> {
+        (0..banks).flat_map(|b| vec![b as u8; bank_size]).collect()
+    }
+
+    #[test]
+    fn wisdom_tree_switches_on_address() {
+        let mut rom = banked_rom(0x8000, 4);
+        rom[0x2000..0x200B].copy_from_slice(b"WISDOM TREE");
+        let mut cart = Cartridge::new(rom).unwrap();
+        assert_eq!(cart.kind, MapperKind::WisdomTree);
+
+        cart.write8(0x0002, 0xFF);
+        assert_eq!(cart.read8(0x0000), 2);
+        assert_eq!(cart.read8(0x7FFF), 2);
+    }
+
+    #[test]
+    fn m161_latches_first_write() {
+        let mut cart = Cartridge::with_mapper(banked_rom(0x8000, 8), MapperKind::M161).unwrap();
+        cart.write8(0x4000, 3);
+        cart.write8(0x4000, 5);
+        assert_eq!(cart.read8(0x0100), 3);
+    }
+
+    #[test]
+    fn sachen_logo_is_scrambled_until_unlocked() {
+        let mut rom = banked_rom(ROM_BANK_SIZE, 4);
+        for (i, byte) in NINTENDO_LOGO.iter().enumerate() {
+            rom[Sachen::scramble(0x0104 + i as u16) as usize] = *byte;
+        }
+        let mut cart = Cartridge::new(rom).unwrap();
+        assert_eq!(cart.kind, MapperKind::SachenMmc1);
+        assert_eq!(cart.read8(0x0104), NINTENDO_LOGO[0]);
+
+        for _ in 0..SACHEN_UNLOCK_EDGES {
+            cart.bus_access(0x8000);
+            cart.bus_access(0x0000);
+        }
+        assert_eq!(cart.read8(0x0104), 0);
+    }
+
+    #[test]
+    fn bbd_scrambles_bank_number() {
+        let mut cart = Cartridge::with_mapper(banked_rom(ROM_BANK_SIZE, 16), MapperKind::Bbd).unwrap();
+        cart.write8(0x2080, 0x03);
+        cart.write8(0x2000, 0b0000_1000);
+        assert_eq!(cart.read8(0x4000), 0b0000_0001);
+    }
+}
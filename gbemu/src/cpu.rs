@@ -1,6 +1,10 @@
 use crate::registers::*;
 use crate::instructions;
-use crate::memory::Memory;
+use crate::memory::{Interrupt, Memory};
+use crate::cartridge::Cartridge;
+use crate::init::{InitPolicy, Rng};
+use crate::model::Model;
+use crate::word::Word;
 
 use crate::log as console_log;
 
@@ -10,30 +14,89 @@ enum RegisterAction {
     Increment,
 }
 
+#[derive(Copy, Clone)]
+enum Alu {
+    Add, Adc, Sub, Sbc, And, Xor, Or, Cp
+}
+
+// Operands of the 0xCB prefixed instructions, in opcode order. None is (HL).
+const PREFIX_OPERANDS: [Option<Register8>; 8] = [
+    Some(Register8::B), Some(Register8::C), Some(Register8::D), Some(Register8::E),
+    Some(Register8::H), Some(Register8::L), None, Some(Register8::A),
+];
+
 
 pub struct LR35902 {
     pub cycle: u64,
     pub registers: Registers,
     pub memory: Memory,
+
+    // Interrupt master enable, and EI's one instruction delay before it takes effect
+    pub ime: bool,
+    ime_pending: bool,
+    pub halted: bool,
+    // Stopped until a selected joypad line goes low, with the clock (and so everything else) halted
+    pub stopped: bool,
+    // HALT with IME off and an interrupt already pending fails to increment PC
+    halt_bug: bool,
+    // Set by the illegal opcodes, which hang the CPU
+    locked: bool,
 }
 
 impl LR35902 {
-    pub fn open(rom: Vec<u8>) -> Self {
-        console_log("Initialising CPU");
-        let mut memory = Memory::new();
-        for (i, value) in rom.into_iter().enumerate() {
-            let addr = (i + 256) as u16;
-            memory.set8(addr, value);
+    pub fn open(rom: Vec<u8>, model: Model) -> Result<Self, String> {
+        Ok(Self::insert(Cartridge::new(rom)?, model))
+    }
+
+    pub fn insert(cartridge: Cartridge, model: Model) -> Self {
+        console_log(format!("Initialising CPU as {model:?}").as_str());
+        let registers = Registers::post_boot(model, &cartridge.header);
+        let mut memory = Memory::new(cartridge, model);
+        // No boot ROM is run, so start from where it would have left things
+        memory.post_boot();
+        Self::with_state(registers, memory)
+    }
+
+    // Power on into a boot ROM, which is mapped over the cartridge until it writes to 0xFF50
+    pub fn boot(cartridge: Cartridge, boot_rom: Vec<u8>, model: Model) -> Self {
+        console_log(format!("Initialising CPU as {model:?} with boot ROM").as_str());
+        let mut memory = Memory::new(cartridge, model);
+        memory.map_boot_rom(boot_rom);
+        Self::with_state(Registers::power_on(), memory)
+    }
+
+    // Set RAM, and the registers if a boot ROM has yet to set them, to their power on contents
+    pub fn initialise(&mut self, policy: &InitPolicy) {
+        console_log(format!("Initialising memory with {:?}, seed {}", policy.pattern, policy.seed).as_str());
+        let mut rng = Rng::new(policy.seed);
+        self.memory.initialise(policy, &mut rng);
+
+        if self.memory.boot_rom_mapped() {
+            let mut word = || Word(policy.byte(&mut rng), policy.byte(&mut rng));
+            self.registers.af = word();
+            self.registers.bc = word();
+            self.registers.de = word();
+            self.registers.hl = word();
+            self.registers.sp = word().get16();
         }
+    }
+
+    fn with_state(registers: Registers, memory: Memory) -> Self {
         Self {
             cycle: 0,
-            registers: Registers::new(),
-            memory: memory,
+            registers,
+            memory,
+            ime: false,
+            ime_pending: false,
+            halted: false,
+            stopped: false,
+            halt_bug: false,
+            locked: false,
         }
     }
 
     fn next_byte(&mut self) -> u8 {
-        let byte = self.memory[self.registers.pc];
+        let byte = self.memory.get8(self.registers.pc);
         self.registers.pc = self.registers.pc.wrapping_add(1);
         byte
     }
@@ -45,21 +108,85 @@ impl LR35902 {
     }
 
     fn fetch(&mut self) -> u8 {
+        if self.halt_bug {
+            self.halt_bug = false;
+            return self.memory.get8(self.registers.pc);
+        }
         self.next_byte()
     }
 
-    fn execute(&mut self, instruction: u8) {
+    // Run one instruction (or interrupt dispatch, or halted cycle), returning the M-cycles taken
+    pub fn step(&mut self) -> u64 {
+        if self.stopped {
+            self.stopped = !self.memory.joypad_held();
+            self.memory.tick_stopped(1);
+            self.cycle += 1;
+            return 1;
+        }
+
+        let cycles_passed = if self.locked {
+            1
+        }
+        else if let Some(cycles) = self.service_interrupts() {
+            cycles
+        }
+        else if self.halted {
+            1
+        }
+        else {
+            // EI takes effect after the instruction following it, unless that's a DI
+            let enable_interrupts = self.ime_pending;
+
+            let instruction = self.fetch();
+            let cycles = self.execute(instruction);
+
+            if enable_interrupts && self.ime_pending {
+                self.ime = true;
+                self.ime_pending = false;
+            }
+            cycles
+        };
+
+        self.cycle += cycles_passed;
+        self.memory.tick(cycles_passed);
+        cycles_passed
+    }
+
+    fn service_interrupts(&mut self) -> Option<u64> {
+        let pending = self.memory.pending_interrupts();
+        if pending == 0 {
+            return None;
+        }
+
+        // Any pending interrupt ends HALT, whether or not it is then serviced
+        self.halted = false;
+        if !self.ime {
+            return None;
+        }
+
+        let interrupt = Interrupt::ALL[pending.trailing_zeros() as usize];
+        self.ime = false;
+        self.memory.acknowledge_interrupt(interrupt);
+        self.push_reg16(Register16::PC);
+        self.registers.pc = interrupt.vector();
+        Some(5)
+    }
+
+    fn execute(&mut self, instruction: u8) -> u64 {
+        #[cfg(feature = "trace")]
         console_log(format!("Executing {instruction:?}").as_str());
-        let cycles_passed = match instruction {
+        match instruction {
 
             instructions::NO_OP => 1,
-            instructions::STOP => todo!(),
+            instructions::STOP => self.stop(),
             instructions::PREFIX => self.execute_prefixed(),
-            instructions::DAA => todo!(),
-            instructions::SCF => todo!(),
+            instructions::DAA => self.decimal_adjust(),
+            instructions::SCF => self.set_carry(),
             instructions::CPL => self.invert_reg8(Register8::A),
             instructions::CCF => self.invert_carry(),
-            instructions::HALT => todo!(),
+            instructions::HALT => self.halt(),
+            instructions::DI => self.disable_interrupts(),
+            instructions::EI => self.enable_interrupts(),
 
             instructions::JR_s8 => self.jump_relative(),
             instructions::JR_Z_s8 => self.conditional_jump_relative(Flag::Zero, true),
@@ -67,6 +194,45 @@ impl LR35902 {
             instructions::JR_NZ_s8 => self.conditional_jump_relative(Flag::Zero, false),
             instructions::JR_NC_s8 => self.conditional_jump_relative(Flag::Carry, false),
 
+            instructions::JP_a16 => self.jump(),
+            instructions::JP_HL => self.jump_to_reg16(Register16::HL),
+            instructions::JP_Z_a16 => self.conditional_jump(Flag::Zero, true),
+            instructions::JP_C_a16 => self.conditional_jump(Flag::Carry, true),
+            instructions::JP_NZ_a16 => self.conditional_jump(Flag::Zero, false),
+            instructions::JP_NC_a16 => self.conditional_jump(Flag::Carry, false),
+
+            instructions::CALL_a16 => self.call(),
+            instructions::CALL_Z_a16 => self.conditional_call(Flag::Zero, true),
+            instructions::CALL_C_a16 => self.conditional_call(Flag::Carry, true),
+            instructions::CALL_NZ_a16 => self.conditional_call(Flag::Zero, false),
+            instructions::CALL_NC_a16 => self.conditional_call(Flag::Carry, false),
+
+            instructions::RET => self.ret(),
+            instructions::RETI => self.ret_enable_interrupts(),
+            instructions::RET_Z => self.conditional_ret(Flag::Zero, true),
+            instructions::RET_C => self.conditional_ret(Flag::Carry, true),
+            instructions::RET_NZ => self.conditional_ret(Flag::Zero, false),
+            instructions::RET_NC => self.conditional_ret(Flag::Carry, false),
+
+            instructions::RST_00H => self.restart(0x00),
+            instructions::RST_08H => self.restart(0x08),
+            instructions::RST_10H => self.restart(0x10),
+            instructions::RST_18H => self.restart(0x18),
+            instructions::RST_20H => self.restart(0x20),
+            instructions::RST_28H => self.restart(0x28),
+            instructions::RST_30H => self.restart(0x30),
+            instructions::RST_38H => self.restart(0x38),
+
+            instructions::PUSH_AF => self.push_reg16(Register16::AF),
+            instructions::PUSH_BC => self.push_reg16(Register16::BC),
+            instructions::PUSH_DE => self.push_reg16(Register16::DE),
+            instructions::PUSH_HL => self.push_reg16(Register16::HL),
+
+            instructions::POP_AF => self.pop_reg16(Register16::AF),
+            instructions::POP_BC => self.pop_reg16(Register16::BC),
+            instructions::POP_DE => self.pop_reg16(Register16::DE),
+            instructions::POP_HL => self.pop_reg16(Register16::HL),
+
             instructions::INC_A => self.increment8(Register8::A),
             instructions::INC_B => self.increment8(Register8::B),
             instructions::INC_C => self.increment8(Register8::C),
@@ -80,7 +246,7 @@ impl LR35902 {
             instructions::DEC_C => self.decrement8(Register8::C),
             instructions::DEC_D => self.decrement8(Register8::D),
             instructions::DEC_E => self.decrement8(Register8::E),
-            instructions::DEC_H => self.decrement8(Register8::E),
+            instructions::DEC_H => self.decrement8(Register8::H),
             instructions::DEC_L => self.decrement8(Register8::L),
 
             instructions::INC_BC => self.increment16(Register16::BC),
@@ -195,36 +361,172 @@ impl LR35902 {
 
             instructions::LD_a16_SP => self.load_reg16_to_mem(Register16::SP),
 
+            instructions::LD_a16_A => self.load_reg_to_mem(Register8::A),
+            instructions::LD_A_a16 => self.load_mem_to_reg(Register8::A),
+            instructions::LDH_a8_A => self.load_reg_to_high_mem(Register8::A),
+            instructions::LDH_A_a8 => self.load_high_mem_to_reg(Register8::A),
+            instructions::LD_aC_A => self.load_reg_to_high_mem_at_reg(Register8::C, Register8::A),
+            instructions::LD_A_aC => self.load_high_mem_at_reg_to_reg(Register8::A, Register8::C),
+
+            instructions::LD_SP_HL => self.load_reg16_to_reg16(Register16::SP, Register16::HL),
+            instructions::LD_HL_SP_ADD_s8 => self.load_sp_offset_to_reg16(Register16::HL),
+
             instructions::RLCA => self.rotate_left_circular(Register8::A),
             instructions::RRCA => self.rotate_right_circular(Register8::A),
 
             instructions::RLA => self.rotate_left(Register8::A),
             instructions::RRA => self.rotate_right(Register8::A),
 
-            instructions::ADD_A_B => self.add(Register8::B),
-            instructions::ADD_A_C => self.add(Register8::C),
+            instructions::ADD_A_B => self.alu_reg(Alu::Add, Register8::B),
+            instructions::ADD_A_C => self.alu_reg(Alu::Add, Register8::C),
+            instructions::ADD_A_D => self.alu_reg(Alu::Add, Register8::D),
+            instructions::ADD_A_E => self.alu_reg(Alu::Add, Register8::E),
+            instructions::ADD_A_H => self.alu_reg(Alu::Add, Register8::H),
+            instructions::ADD_A_L => self.alu_reg(Alu::Add, Register8::L),
+            instructions::ADD_A_aHL => self.alu_mem_at_reg(Alu::Add, Register16::HL),
+            instructions::ADD_A_A => self.alu_reg(Alu::Add, Register8::A),
+            instructions::ADD_A_d8 => self.alu_byte(Alu::Add),
+
+            instructions::ADC_A_B => self.alu_reg(Alu::Adc, Register8::B),
+            instructions::ADC_A_C => self.alu_reg(Alu::Adc, Register8::C),
+            instructions::ADC_A_D => self.alu_reg(Alu::Adc, Register8::D),
+            instructions::ADC_A_E => self.alu_reg(Alu::Adc, Register8::E),
+            instructions::ADC_A_H => self.alu_reg(Alu::Adc, Register8::H),
+            instructions::ADC_A_L => self.alu_reg(Alu::Adc, Register8::L),
+            instructions::ADC_A_aHL => self.alu_mem_at_reg(Alu::Adc, Register16::HL),
+            instructions::ADC_A_A => self.alu_reg(Alu::Adc, Register8::A),
+            instructions::ADC_A_d8 => self.alu_byte(Alu::Adc),
+
+            instructions::SUB_B => self.alu_reg(Alu::Sub, Register8::B),
+            instructions::SUB_C => self.alu_reg(Alu::Sub, Register8::C),
+            instructions::SUB_D => self.alu_reg(Alu::Sub, Register8::D),
+            instructions::SUB_E => self.alu_reg(Alu::Sub, Register8::E),
+            instructions::SUB_H => self.alu_reg(Alu::Sub, Register8::H),
+            instructions::SUB_L => self.alu_reg(Alu::Sub, Register8::L),
+            instructions::SUB_aHL => self.alu_mem_at_reg(Alu::Sub, Register16::HL),
+            instructions::SUB_A => self.alu_reg(Alu::Sub, Register8::A),
+            instructions::SUB_d8 => self.alu_byte(Alu::Sub),
+
+            instructions::SBC_A_B => self.alu_reg(Alu::Sbc, Register8::B),
+            instructions::SBC_A_C => self.alu_reg(Alu::Sbc, Register8::C),
+            instructions::SBC_A_D => self.alu_reg(Alu::Sbc, Register8::D),
+            instructions::SBC_A_E => self.alu_reg(Alu::Sbc, Register8::E),
+            instructions::SBC_A_H => self.alu_reg(Alu::Sbc, Register8::H),
+            instructions::SBC_A_L => self.alu_reg(Alu::Sbc, Register8::L),
+            instructions::SBC_A_aHL => self.alu_mem_at_reg(Alu::Sbc, Register16::HL),
+            instructions::SBC_A_A => self.alu_reg(Alu::Sbc, Register8::A),
+            instructions::SBC_A_d8 => self.alu_byte(Alu::Sbc),
+
+            instructions::AND_B => self.alu_reg(Alu::And, Register8::B),
+            instructions::AND_C => self.alu_reg(Alu::And, Register8::C),
+            instructions::AND_D => self.alu_reg(Alu::And, Register8::D),
+            instructions::AND_E => self.alu_reg(Alu::And, Register8::E),
+            instructions::AND_H => self.alu_reg(Alu::And, Register8::H),
+            instructions::AND_L => self.alu_reg(Alu::And, Register8::L),
+            instructions::AND_aHL => self.alu_mem_at_reg(Alu::And, Register16::HL),
+            instructions::AND_A => self.alu_reg(Alu::And, Register8::A),
+            instructions::AND_d8 => self.alu_byte(Alu::And),
+
+            instructions::XOR_B => self.alu_reg(Alu::Xor, Register8::B),
+            instructions::XOR_C => self.alu_reg(Alu::Xor, Register8::C),
+            instructions::XOR_D => self.alu_reg(Alu::Xor, Register8::D),
+            instructions::XOR_E => self.alu_reg(Alu::Xor, Register8::E),
+            instructions::XOR_H => self.alu_reg(Alu::Xor, Register8::H),
+            instructions::XOR_L => self.alu_reg(Alu::Xor, Register8::L),
+            instructions::XOR_aHL => self.alu_mem_at_reg(Alu::Xor, Register16::HL),
+            instructions::XOR_A => self.alu_reg(Alu::Xor, Register8::A),
+            instructions::XOR_d8 => self.alu_byte(Alu::Xor),
+
+            instructions::OR_B => self.alu_reg(Alu::Or, Register8::B),
+            instructions::OR_C => self.alu_reg(Alu::Or, Register8::C),
+            instructions::OR_D => self.alu_reg(Alu::Or, Register8::D),
+            instructions::OR_E => self.alu_reg(Alu::Or, Register8::E),
+            instructions::OR_H => self.alu_reg(Alu::Or, Register8::H),
+            instructions::OR_L => self.alu_reg(Alu::Or, Register8::L),
+            instructions::OR_aHL => self.alu_mem_at_reg(Alu::Or, Register16::HL),
+            instructions::OR_A => self.alu_reg(Alu::Or, Register8::A),
+            instructions::OR_d8 => self.alu_byte(Alu::Or),
+
+            instructions::CP_B => self.alu_reg(Alu::Cp, Register8::B),
+            instructions::CP_C => self.alu_reg(Alu::Cp, Register8::C),
+            instructions::CP_D => self.alu_reg(Alu::Cp, Register8::D),
+            instructions::CP_E => self.alu_reg(Alu::Cp, Register8::E),
+            instructions::CP_H => self.alu_reg(Alu::Cp, Register8::H),
+            instructions::CP_L => self.alu_reg(Alu::Cp, Register8::L),
+            instructions::CP_aHL => self.alu_mem_at_reg(Alu::Cp, Register16::HL),
+            instructions::CP_A => self.alu_reg(Alu::Cp, Register8::A),
+            instructions::CP_d8 => self.alu_byte(Alu::Cp),
+
             instructions::ADD_HL_BC => self.add16(Register16::HL, Register16::BC),
             instructions::ADD_HL_DE => self.add16(Register16::HL, Register16::DE),
             instructions::ADD_HL_HL => self.add16(Register16::HL, Register16::HL),
             instructions::ADD_HL_SP => self.add16(Register16::HL, Register16::SP),
+            instructions::ADD_SP_s8 => self.add_offset_to_sp(),
 
-            0x80..=0xFF => todo!(),
-        };
-
-        self.cycle += cycles_passed;
+            0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD => self.lock_up(instruction),
+        }
     }
 
     fn execute_prefixed(&mut self) -> u64 {
         let instruction = self.next_byte();
-        match instruction {
-            0x00..=0xFF => todo!()
+        let operand = PREFIX_OPERANDS[(instruction & 0x07) as usize];
+        let bit = (instruction >> 3) & 0x07;
+
+        let value = match operand {
+            Some(reg) => self.registers.get8(reg),
+            None => self.memory.get8(self.registers.get16(Register16::HL)),
+        };
+
+        let result = match instruction >> 6 {
+            0b00 => Some(self.shift_rotate(bit, value)),
+            0b01 => {
+                self.registers.set_flag(Flag::Zero, value & (1 << bit) == 0);
+                self.registers.set_flag(Flag::Subtract, false);
+                self.registers.set_flag(Flag::HalfCarry, true);
+                None
+            }
+            0b10 => Some(value & !(1 << bit)),
+            _ => Some(value | (1 << bit)),
+        };
+
+        if let Some(result) = result {
+            match operand {
+                Some(reg) => self.registers.set8(reg, result),
+                None => {
+                    let addr = self.registers.get16(Register16::HL);
+                    self.memory.set8(addr, result);
+                }
+            }
+        }
+
+        match (operand, result) {
+            (Some(_), _) => 2,
+            (None, None) => 3,
+            (None, Some(_)) => 4,
         }
     }
 
+    // RLC, RRC, RL, RR, SLA, SRA, SWAP and SRL, selected by bits 3-5 of the prefixed opcode
+    fn shift_rotate(&mut self, op: u8, v: u8) -> u8 {
+        let carry_in = self.registers.get_flag(Flag::Carry) as u8;
+        let (result, carry) = match op {
+            0 => (v.rotate_left(1), v & 0x80 != 0),
+            1 => (v.rotate_right(1), v & 0x01 != 0),
+            2 => ((v << 1) | carry_in, v & 0x80 != 0),
+            3 => ((v >> 1) | (carry_in << 7), v & 0x01 != 0),
+            4 => (v << 1, v & 0x80 != 0),
+            5 => ((v >> 1) | (v & 0x80), v & 0x01 != 0),
+            6 => (v.rotate_left(4), false),
+            _ => (v >> 1, v & 0x01 != 0),
+        };
+        self.registers.set_flags(result == 0, false, false, carry);
+        result
+    }
+
+    // Runs until a blocked VRAM or OAM access breaks, if asked to
     pub fn run(&mut self) {
-        loop {
-            let instruction = self.fetch();
-            self.execute(instruction);
+        while self.memory.locked_access().is_none() {
+            self.step();
         }
     }
 
@@ -232,8 +534,7 @@ impl LR35902 {
     fn run_n(&mut self, v: u16) {
         console_log(format!("Running instructions {} to {} of program", self.registers.pc, self.registers.pc.wrapping_add(v)).as_str());
         for _ in 0..v {
-            let instruction = self.fetch();
-            self.execute(instruction);
+            self.step();
         }
     }
     
@@ -257,15 +558,21 @@ impl LR35902 {
         1
     }
 
+    fn load_reg16_to_reg16(&mut self, dest: Register16, src: Register16) -> u64 {
+        let v = self.registers.get16(src);
+        self.registers.set16(dest, v);
+        2
+    }
+
     fn load_reg_to_mem_at_reg(&mut self, dest: Register16, src: Register8, reg_action: RegisterAction) -> u64 {
         let addr = self.registers.get16(dest);
         let value = self.registers.get8(src);
-        self.memory[addr] = value;
+        self.memory.set8(addr, value);
 
         match reg_action {
             RegisterAction::Nothing => (),
-            RegisterAction::Decrement => self.registers.set16(dest, addr - 1),
-            RegisterAction::Increment => self.registers.set16(dest, addr + 1),
+            RegisterAction::Decrement => self.registers.set16(dest, addr.wrapping_sub(1)),
+            RegisterAction::Increment => self.registers.set16(dest, addr.wrapping_add(1)),
         }
 
         2
@@ -273,21 +580,62 @@ impl LR35902 {
 
     fn load_reg16_to_mem(&mut self, src: Register16) -> u64 {
         let addr = self.next_word();
-        let (lsb, msb) = self.registers.get8_8(src);
-        self.memory[addr] = lsb;
-        self.memory[addr + 1] = msb;
+        let value = self.registers.get16(src);
+        self.memory.set16(addr, value);
         5
     }
 
+    fn load_reg_to_mem(&mut self, src: Register8) -> u64 {
+        let addr = self.next_word();
+        let value = self.registers.get8(src);
+        self.memory.set8(addr, value);
+        4
+    }
+
+    fn load_mem_to_reg(&mut self, dest: Register8) -> u64 {
+        let addr = self.next_word();
+        let value = self.memory.get8(addr);
+        self.registers.set8(dest, value);
+        4
+    }
+
+    fn load_reg_to_high_mem(&mut self, src: Register8) -> u64 {
+        let addr = 0xFF00 | self.next_byte() as u16;
+        let value = self.registers.get8(src);
+        self.memory.set8(addr, value);
+        3
+    }
+
+    fn load_high_mem_to_reg(&mut self, dest: Register8) -> u64 {
+        let addr = 0xFF00 | self.next_byte() as u16;
+        let value = self.memory.get8(addr);
+        self.registers.set8(dest, value);
+        3
+    }
+
+    fn load_reg_to_high_mem_at_reg(&mut self, offset: Register8, src: Register8) -> u64 {
+        let addr = 0xFF00 | self.registers.get8(offset) as u16;
+        let value = self.registers.get8(src);
+        self.memory.set8(addr, value);
+        2
+    }
+
+    fn load_high_mem_at_reg_to_reg(&mut self, dest: Register8, offset: Register8) -> u64 {
+        let addr = 0xFF00 | self.registers.get8(offset) as u16;
+        let value = self.memory.get8(addr);
+        self.registers.set8(dest, value);
+        2
+    }
+
     fn load_mem_at_reg_to_reg(&mut self, dest: Register8, src: Register16, reg_action: RegisterAction) -> u64 {
         let addr = self.registers.get16(src);
-        let value = self.memory[addr];
+        let value = self.memory.get8(addr);
         self.registers.set8(dest, value);
 
         match reg_action {
             RegisterAction::Nothing => (),
-            RegisterAction::Decrement => self.registers.set16(src, addr - 1),
-            RegisterAction::Increment => self.registers.set16(src, addr + 1),
+            RegisterAction::Decrement => self.registers.set16(src, addr.wrapping_sub(1)),
+            RegisterAction::Increment => self.registers.set16(src, addr.wrapping_add(1)),
         }
 
         2
@@ -296,79 +644,116 @@ impl LR35902 {
     fn load_byte_to_mem_at_reg(&mut self, reg: Register16) -> u64 {
         let value = self.next_byte();
         let addr = self.registers.get16(reg);
-        self.memory[addr] = value;
+        self.memory.set8(addr, value);
+        3
+    }
+
+    // SP plus a signed byte operand; flags come from the unsigned add of the low bytes
+    fn sp_plus_offset(&mut self) -> u16 {
+        let offset = self.next_byte() as i8 as u16;
+        let sp = self.registers.sp;
+        let half_carry = (sp & 0x000F) + (offset & 0x000F) > 0x000F;
+        let carry = (sp & 0x00FF) + (offset & 0x00FF) > 0x00FF;
+        self.registers.set_flags(false, false, half_carry, carry);
+        sp.wrapping_add(offset)
+    }
+
+    fn load_sp_offset_to_reg16(&mut self, dest: Register16) -> u64 {
+        let value = self.sp_plus_offset();
+        self.registers.set16(dest, value);
         3
     }
 
+    fn add_offset_to_sp(&mut self) -> u64 {
+        self.registers.sp = self.sp_plus_offset();
+        4
+    }
+
     fn increment8(&mut self, reg: Register8) -> u64 {
         let v = self.registers.get8(reg);
 
         let half_carry = (v & 0x0F) + 1 > 0x0F;
-        let (v, carry) = v.overflowing_add(1);
+        let v = v.wrapping_add(1);
         self.registers.set8(reg, v);
 
-        self.registers.set_flags(
-            v == 0,
-            false,
-            half_carry,
-            carry,
-        );
+        self.registers.set_flag(Flag::Zero, v == 0);
+        self.registers.set_flag(Flag::Subtract, false);
+        self.registers.set_flag(Flag::HalfCarry, half_carry);
 
         1
     }
 
     fn increment16(&mut self, reg: Register16) -> u64 {
         let v = self.registers.get16(reg);
-        const BIT_11_MASK: u16 = 0b0000_1111_1111_1111;
-        let half_carry: bool = (v & BIT_11_MASK) + 1 > BIT_11_MASK;
-        let (v, carry) = v.overflowing_add(1);
-        self.registers.set16(reg, v);
-
-        self.registers.set_flags(
-            v == 0,
-            false,
-            half_carry,
-            carry,
-        );
-
-        1
+        self.registers.set16(reg, v.wrapping_add(1));
+        2
     }
 
     fn increment_mem_at_reg16(&mut self, reg: Register16) -> u64 {
         let addr = self.registers.get16(reg);
-        let v = self.memory[addr];
+        let v = self.memory.get8(addr);
 
         let half_carry = (v & 0x0F) + 1 > 0x0F;
-        let (v, carry) = v.overflowing_add(1);
-        self.memory[addr] = v;
+        let v = v.wrapping_add(1);
+        self.memory.set8(addr, v);
 
-        self.registers.set_flags(
-            v == 0,
-            false,
-            half_carry,
-            carry,
-        );
+        self.registers.set_flag(Flag::Zero, v == 0);
+        self.registers.set_flag(Flag::Subtract, false);
+        self.registers.set_flag(Flag::HalfCarry, half_carry);
 
         3
     }
 
-    fn add(&mut self, reg: Register8) -> u64 {
+    fn alu(&mut self, op: Alu, v: u8) {
         let a = self.registers.af.0;
-        let v = self.registers.get8(reg);
-        let half_carry = (a & 0x0F) + (v & 0x0F) > 0x0F;
-        let (a, carry) = a.overflowing_add(v);
-        self.registers.af.0 = a;
+        let carry_in = match op {
+            Alu::Adc | Alu::Sbc => self.registers.get_flag(Flag::Carry) as u8,
+            _ => 0,
+        };
 
-        self.registers.set_flags(
-            a == 0,
-            false,
-            half_carry,
-            carry,
-        );
+        let (result, half_carry, carry) = match op {
+            Alu::Add | Alu::Adc => {
+                let sum = a as u16 + v as u16 + carry_in as u16;
+                (sum as u8, (a & 0x0F) + (v & 0x0F) + carry_in > 0x0F, sum > 0xFF)
+            }
+            Alu::Sub | Alu::Sbc | Alu::Cp => {
+                let difference = a.wrapping_sub(v).wrapping_sub(carry_in);
+                let half_carry = (a & 0x0F) < (v & 0x0F) + carry_in;
+                let carry = (a as u16) < v as u16 + carry_in as u16;
+                (difference, half_carry, carry)
+            }
+            Alu::And => (a & v, true, false),
+            Alu::Xor => (a ^ v, false, false),
+            Alu::Or => (a | v, false, false),
+        };
+
+        let subtract = matches!(op, Alu::Sub | Alu::Sbc | Alu::Cp);
+        self.registers.set_flags(result == 0, subtract, half_carry, carry);
+
+        if !matches!(op, Alu::Cp) {
+            self.registers.af.0 = result;
+        }
+    }
 
+    fn alu_reg(&mut self, op: Alu, reg: Register8) -> u64 {
+        let v = self.registers.get8(reg);
+        self.alu(op, v);
         1
     }
 
+    fn alu_mem_at_reg(&mut self, op: Alu, reg: Register16) -> u64 {
+        let addr = self.registers.get16(reg);
+        let v = self.memory.get8(addr);
+        self.alu(op, v);
+        2
+    }
+
+    fn alu_byte(&mut self, op: Alu) -> u64 {
+        let v = self.next_byte();
+        self.alu(op, v);
+        2
+    }
+
     fn add16(&mut self, left: Register16, right: Register16) -> u64 {
         // 16bit add- halfcarry is from bit11 to 12
         let a = self.registers.get16(left);
@@ -380,12 +765,9 @@ impl LR35902 {
         let (a, carry) = a.overflowing_add(b);
         self.registers.set16(left, a);
 
-        self.registers.set_flags(
-            a == 0,
-            false,
-            half_carry,
-            carry,
-        );
+        self.registers.set_flag(Flag::Subtract, false);
+        self.registers.set_flag(Flag::HalfCarry, half_carry);
+        self.registers.set_flag(Flag::Carry, carry);
 
         2
     }
@@ -395,129 +777,150 @@ impl LR35902 {
     fn decrement8(&mut self, reg: Register8) -> u64 {
         let v = self.registers.get8(reg);
 
-        let half_carry = (v & 0x0F) - 1 > 0x0F;
-        let (v, carry) = v.overflowing_sub(1);
+        let half_carry = v & 0x0F == 0;
+        let v = v.wrapping_sub(1);
         self.registers.set8(reg, v);
 
-        self.registers.set_flags(
-            v == 0,
-            true,
-            half_carry,
-            carry,
-        );
+        self.registers.set_flag(Flag::Zero, v == 0);
+        self.registers.set_flag(Flag::Subtract, true);
+        self.registers.set_flag(Flag::HalfCarry, half_carry);
 
         1
     }
 
     fn decrement16(&mut self, reg: Register16) -> u64 {
         let v = self.registers.get16(reg);
-        const BIT_11_MASK: u16 = 0b0000_1111_1111_1111;
-        let half_carry: bool = (v & BIT_11_MASK) - 1 > BIT_11_MASK;
-        let (v, carry) = v.overflowing_sub(1);
-        self.registers.set16(reg, v);
-
-        self.registers.set_flags(
-            v == 0,
-            true,
-            half_carry,
-            carry,
-        );
-
-        1
+        self.registers.set16(reg, v.wrapping_sub(1));
+        2
     }
 
     fn decrement_mem_at_reg16(&mut self, reg: Register16) -> u64 {
         let addr = self.registers.get16(reg);
-        let v = self.memory[addr];
+        let v = self.memory.get8(addr);
 
-        let half_carry = (v & 0x0F) - 1 > 0x0F;
-        let (v, carry) = v.overflowing_sub(1);
-        self.memory[addr] = v;
+        let half_carry = v & 0x0F == 0;
+        let v = v.wrapping_sub(1);
+        self.memory.set8(addr, v);
 
-        self.registers.set_flags(
-            v == 0,
-            true,
-            half_carry,
-            carry,
-        );
+        self.registers.set_flag(Flag::Zero, v == 0);
+        self.registers.set_flag(Flag::Subtract, true);
+        self.registers.set_flag(Flag::HalfCarry, half_carry);
 
-        1
+        3
     }
 
-    fn sub8(&mut self, reg: Register8) -> u64 {
-        let a = self.registers.af.0;
-        let v = self.registers.get8(reg);
+    fn decimal_adjust(&mut self) -> u64 {
+        // https://forums.nesdev.org/viewtopic.php?t=15944
+        let mut a = self.registers.af.0;
+        let mut carry = self.registers.get_flag(Flag::Carry);
+        let half_carry = self.registers.get_flag(Flag::HalfCarry);
+
+        if !self.registers.get_flag(Flag::Subtract) {
+            if carry || a > 0x99 {
+                a = a.wrapping_add(0x60);
+                carry = true;
+            }
+            if half_carry || (a & 0x0F) > 0x09 {
+                a = a.wrapping_add(0x06);
+            }
+        }
+        else {
+            if carry {
+                a = a.wrapping_sub(0x60);
+            }
+            if half_carry {
+                a = a.wrapping_sub(0x06);
+            }
+        }
 
-        let half_carry = (a & 0x0F) - (v & 0x0F) > 0x0F;
-        let (a, carry) = a.overflowing_sub(v);
         self.registers.af.0 = a;
-
-        self.registers.set_flags(
-            v == 0,
-            true,
-            half_carry,
-            carry,
-        );
-
+        self.registers.set_flag(Flag::Zero, a == 0);
+        self.registers.set_flag(Flag::HalfCarry, false);
+        self.registers.set_flag(Flag::Carry, carry);
         1
     }
 
-    fn sub_mem_at_reg(&mut self, reg: Register16) -> u64 {
-        let addr = self.registers.get16(reg);
-        let a = self.registers.af.0;
-        let v = self.memory[addr];
+    fn jump_relative(&mut self) -> u64 {
+        let s8 = self.next_byte() as i8;
+        self.registers.pc = self.registers.pc.wrapping_add(s8 as u16);
+        3
+    }
 
-        let half_carry = (a & 0x0F) - (v & 0x0F) > 0x0F;
-        let (a, carry) = a.overflowing_sub(v);
-        self.registers.af.0 = a;
+    fn conditional_jump_relative(&mut self, flag: Flag, expected_value: bool) -> u64 {
+        let s8 = self.next_byte() as i8;
+
+        if self.registers.get_flag(flag) == expected_value {
+            self.registers.pc = self.registers.pc.wrapping_add(s8 as u16);
+            3
+        }
+        else {
+            2
+        }
 
-        self.registers.set_flags(
-            v == 0,
-            true,
-            half_carry,
-            carry,
-        );
+    }
 
-        2
+    fn jump(&mut self) -> u64 {
+        self.registers.pc = self.next_word();
+        4
     }
 
-    fn sub_byte(&mut self) -> u64 {
-        let a = self.registers.af.0;
-        let v = self.next_byte();
+    fn jump_to_reg16(&mut self, reg: Register16) -> u64 {
+        self.registers.pc = self.registers.get16(reg);
+        1
+    }
 
-        let half_carry = (a & 0x0F) - (v & 0x0F) > 0x0F;
-        let (a, carry) = a.overflowing_sub(v);
-        self.registers.af.0 = a;
+    fn conditional_jump(&mut self, flag: Flag, expected_value: bool) -> u64 {
+        let addr = self.next_word();
+
+        if self.registers.get_flag(flag) == expected_value {
+            self.registers.pc = addr;
+            4
+        }
+        else {
+            3
+        }
+    }
 
-        self.registers.set_flags(
-            v == 0,
-            true,
-            half_carry,
-            carry,
-        );
+    fn call(&mut self) -> u64 {
+        let addr = self.next_word();
+        self.push_reg16(Register16::PC);
+        self.registers.pc = addr;
+        6
+    }
 
-        2
+    fn conditional_call(&mut self, flag: Flag, expected_value: bool) -> u64 {
+        if self.registers.get_flag(flag) == expected_value {
+            self.call()
+        }
+        else {
+            self.next_word();
+            3
+        }
     }
 
-    fn jump_relative(&mut self) -> u64 {
-        let s8 = self.next_byte() as i32;
-        let new_pc = (self.registers.pc as i32) + s8;
-        self.registers.pc = new_pc as u16;
-        3
+    fn ret(&mut self) -> u64 {
+        self.pop_reg16(Register16::PC);
+        4
     }
 
-    fn conditional_jump_relative(&mut self, flag: Flag, expected_value: bool) -> u64 {
-        let s8 = self.next_byte() as i32;
+    fn ret_enable_interrupts(&mut self) -> u64 {
+        self.ime = true;
+        self.ret()
+    }
 
+    fn conditional_ret(&mut self, flag: Flag, expected_value: bool) -> u64 {
         if self.registers.get_flag(flag) == expected_value {
-            let new_pc = (self.registers.pc as i32) + s8;
-            self.registers.pc = new_pc as u16;
-            3
+            self.ret() + 1
         }
         else {
             2
         }
+    }
 
+    fn restart(&mut self, addr: u16) -> u64 {
+        self.push_reg16(Register16::PC);
+        self.registers.pc = addr;
+        4
     }
 
     fn rotate_left_circular(&mut self, reg: Register8) -> u64 {
@@ -552,7 +955,7 @@ impl LR35902 {
         let v = self.registers.get8(reg);
         let new_carry = v & 0b0000_0001;
         let new_msb = self.registers.get_flag(Flag::Carry) as u8;
-        let v = (v << 1) | (new_msb << 7);
+        let v = (v >> 1) | (new_msb << 7);
         self.registers.set_flags(false, false, false, new_carry == 1);
         self.registers.set8(reg, v);
         1
@@ -560,22 +963,28 @@ impl LR35902 {
 
     // Stack ops
     fn pop_reg16(&mut self, reg: Register16) -> u64 {
-        self.registers.sp += 1;
-        let lsb = self.memory[self.registers.sp];
-        self.registers.sp += 1;
-        let msb = self.memory[self.registers.sp];
+        let lsb = self.memory.get8(self.registers.sp);
+        self.registers.sp = self.registers.sp.wrapping_add(1);
+        let msb = self.memory.get8(self.registers.sp);
+        self.registers.sp = self.registers.sp.wrapping_add(1);
         self.registers.set8_8(reg, lsb, msb);
+
+        // The low nibble of F doesn't exist
+        if let Register16::AF = reg {
+            let f = self.registers.get8(Register8::F);
+            self.registers.set8(Register8::F, f & 0xF0);
+        }
         3
     }
 
     fn push_reg16(&mut self, reg: Register16) -> u64 {
         let (lsb, msb) = self.registers.get8_8(reg);
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
         let addr = self.registers.sp;
-        self.memory[addr] = msb;
-        self.registers.sp -= 1;
+        self.memory.set8(addr, msb);
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
         let addr = self.registers.sp;
-        self.memory[addr] = lsb;
-        self.registers.sp -= 1;
+        self.memory.set8(addr, lsb);
         4
     }
 
@@ -588,10 +997,56 @@ impl LR35902 {
         1
     }
 
+    fn set_carry(&mut self) -> u64 {
+        self.registers.set_flag(Flag::Subtract, false);
+        self.registers.set_flag(Flag::HalfCarry, false);
+        self.registers.set_flag(Flag::Carry, true);
+        1
+    }
+
     fn invert_reg8(&mut self, reg: Register8) -> u64 {
         let v = self.registers.get8(reg);
         self.registers.set8(reg, !v);
-        self.registers.set_flags(false, true, true, false);
+        self.registers.set_flag(Flag::Subtract, true);
+        self.registers.set_flag(Flag::HalfCarry, true);
+        1
+    }
+
+    // Interrupt control
+    fn halt(&mut self) -> u64 {
+        if !self.ime && self.memory.pending_interrupts() != 0 {
+            self.halt_bug = true;
+        }
+        else {
+            self.halted = true;
+        }
+        1
+    }
+
+    // STOP is followed by a byte it skips. A button already held makes it a NOP, and on the CGB
+    // a speed switch prepared in KEY1 takes its place.
+    fn stop(&mut self) -> u64 {
+        self.next_byte();
+        if !self.memory.joypad_held() && !self.memory.stop() {
+            self.stopped = true;
+        }
+        1
+    }
+
+    fn disable_interrupts(&mut self) -> u64 {
+        self.ime = false;
+        self.ime_pending = false;
+        1
+    }
+
+    fn enable_interrupts(&mut self) -> u64 {
+        self.ime_pending = true;
+        1
+    }
+
+    fn lock_up(&mut self, instruction: u8) -> u64 {
+        console_log(format!("Illegal instruction {instruction:#04x} at {:#06x}, CPU locked up", self.registers.pc.wrapping_sub(1)).as_str());
+        self.locked = true;
         1
     }
 }
@@ -600,20 +1055,401 @@ impl LR35902 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::memory::KEY1;
+    use crate::ppu;
 
     #[test]
     fn test() {
-        let program: Vec<u8> = vec![
+        // Execution starts at the cartridge entry point, 0x0100
+        let mut program: Vec<u8> = vec![0; 0x100];
+        program.extend([
             instructions::NO_OP,
             instructions::ADD_A_B,
             instructions::LD_C_d8, 0x12,
             instructions::ADD_A_C,
-        ];
-        let mut cpu = LR35902::open(program);
+        ]);
+        let mut cpu = LR35902::open(program, Model::Dmg).unwrap();
         cpu.run_n(2);
         assert_eq!(cpu.registers.af.0, 0x01);
         cpu.run_n(2);
         assert_eq!(cpu.registers.get8(Register8::C), 0x12);
         assert_eq!(cpu.registers.af.0, 0x13);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn loop_and_call() {
+        let mut program: Vec<u8> = vec![0; 0x100];
+        program.extend([
+            instructions::LD_B_d8, 0x03,
+            instructions::CALL_a16, 0x0A, 0x01,
+            instructions::DEC_B,
+            instructions::JR_NZ_s8, 0xFA,
+            instructions::HALT,
+            instructions::NO_OP,
+            // 0x010A: A += 2
+            instructions::INC_A,
+            instructions::INC_A,
+            instructions::RET,
+        ]);
+        let mut cpu = LR35902::open(program, Model::Dmg).unwrap();
+        while !cpu.halted {
+            cpu.step();
+        }
+        assert_eq!(cpu.registers.get8(Register8::B), 0);
+        assert_eq!(cpu.registers.af.0, 0x07);
+        assert_eq!(cpu.registers.sp, 0xFFFE);
+    }
+
+    #[test]
+    fn prefixed_bit_and_swap() {
+        let mut program: Vec<u8> = vec![0; 0x100];
+        program.extend([
+            instructions::LD_H_d8, 0x9F,
+            instructions::PREFIX, 0x7C, // BIT 7,H
+            instructions::PREFIX, 0x34, // SWAP H
+        ]);
+        let mut cpu = LR35902::open(program, Model::Dmg).unwrap();
+        cpu.run_n(2);
+        assert!(!cpu.registers.get_flag(Flag::Zero));
+        cpu.run_n(1);
+        assert_eq!(cpu.registers.get8(Register8::H), 0xF9);
+    }
+
+    #[test]
+    fn ei_is_delayed_an_instruction() {
+        let mut program: Vec<u8> = vec![0; 0x100];
+        program.extend([instructions::EI, instructions::NO_OP, instructions::NO_OP]);
+        let mut cpu = LR35902::open(program, Model::Dmg).unwrap();
+        cpu.run_n(1);
+        assert!(!cpu.ime);
+        cpu.run_n(1);
+        assert!(cpu.ime);
+    }
+
+    #[test]
+    fn di_cancels_ei() {
+        let mut program: Vec<u8> = vec![0; 0x100];
+        program.extend([instructions::EI, instructions::DI, instructions::NO_OP]);
+        let mut cpu = LR35902::open(program, Model::Dmg).unwrap();
+        cpu.run_n(3);
+        assert!(!cpu.ime);
+        assert!(!cpu.ime_pending);
+    }
+
+    #[test]
+    fn stop_switches_speed_once_prepared() {
+        let mut program: Vec<u8> = vec![0; 0x100];
+        program.extend([
+            instructions::LD_A_d8, 0x01,
+            instructions::LDH_a8_A, 0x4D,
+            instructions::STOP, 0x00,
+        ]);
+        program.resize(0x8000, instructions::NO_OP);
+
+        // CPU M-cycles from one LY change to the next
+        fn line_cycles(cpu: &mut LR35902) -> u64 {
+            let ly = cpu.memory.get8(ppu::LY);
+            while cpu.memory.get8(ppu::LY) == ly {
+                cpu.step();
+            }
+            let (ly, start) = (cpu.memory.get8(ppu::LY), cpu.cycle);
+            while cpu.memory.get8(ppu::LY) == ly {
+                cpu.step();
+            }
+            cpu.cycle - start
+        }
+
+        let mut cpu = LR35902::open(vec![0; 0x8000], Model::Cgb).unwrap();
+        assert_eq!(line_cycles(&mut cpu), 114);
+
+        let mut cpu = LR35902::open(program.clone(), Model::Cgb).unwrap();
+        assert_eq!(cpu.memory.get8(KEY1), 0x7E);
+        cpu.run_n(2);
+        assert_eq!(cpu.memory.get8(KEY1), 0x7F);
+        cpu.run_n(1);
+        assert!(!cpu.stopped);
+        assert_eq!(cpu.memory.get8(KEY1), 0xFE);
+        assert_eq!(line_cycles(&mut cpu), 228);
+
+        // Without KEY1 STOP stops as usual
+        let mut cpu = LR35902::open(program, Model::Dmg).unwrap();
+        cpu.run_n(3);
+        assert!(cpu.stopped);
+        assert_eq!(cpu.memory.get8(KEY1), 0xFF);
+    }
+
+    // A DMG past the boot ROM, about to run code from 0x0100
+    fn running(code: &[u8]) -> LR35902 {
+        let mut program: Vec<u8> = vec![0; 0x100];
+        program.extend_from_slice(code);
+        program.resize(0x8000, instructions::NO_OP);
+        LR35902::open(program, Model::Dmg).unwrap()
+    }
+
+    fn flags(cpu: &LR35902) -> u8 {
+        cpu.registers.get8(Register8::F)
+    }
+
+    #[test]
+    fn add_and_adc_flags() {
+        let mut cpu = running(&[
+            instructions::LD_A_d8, 0x3A,
+            instructions::LD_B_d8, 0xC6,
+            instructions::ADD_A_B,
+            instructions::ADC_A_d8, 0x0F,
+        ]);
+        cpu.run_n(3);
+        assert_eq!(cpu.registers.get8(Register8::A), 0x00);
+        assert_eq!(flags(&cpu), 0xB0);
+        cpu.run_n(1);
+        assert_eq!(cpu.registers.get8(Register8::A), 0x10);
+        assert_eq!(flags(&cpu), 0x20);
+    }
+
+    #[test]
+    fn sub_sbc_and_cp_flags() {
+        let mut cpu = running(&[
+            instructions::LD_A_d8, 0x3E,
+            instructions::CP_d8, 0x40,
+            instructions::SUB_d8, 0x0F,
+            instructions::SCF,
+            instructions::SBC_A_d8, 0x2E,
+        ]);
+        cpu.run_n(2);
+        assert_eq!(cpu.registers.get8(Register8::A), 0x3E);
+        assert_eq!(flags(&cpu), 0x50);
+        cpu.run_n(1);
+        assert_eq!(cpu.registers.get8(Register8::A), 0x2F);
+        assert_eq!(flags(&cpu), 0x60);
+        cpu.run_n(2);
+        assert_eq!(cpu.registers.get8(Register8::A), 0x00);
+        assert_eq!(flags(&cpu), 0xC0);
+    }
+
+    #[test]
+    fn logic_flags() {
+        let mut cpu = running(&[
+            instructions::LD_A_d8, 0x5A,
+            instructions::AND_d8, 0xA5,
+            instructions::LD_A_d8, 0x5A,
+            instructions::OR_d8, 0x0F,
+            instructions::XOR_A,
+        ]);
+        cpu.run_n(2);
+        assert_eq!(cpu.registers.get8(Register8::A), 0x00);
+        assert_eq!(flags(&cpu), 0xA0);
+        cpu.run_n(2);
+        assert_eq!(cpu.registers.get8(Register8::A), 0x5F);
+        assert_eq!(flags(&cpu), 0x00);
+        cpu.run_n(1);
+        assert_eq!(cpu.registers.get8(Register8::A), 0x00);
+        assert_eq!(flags(&cpu), 0x80);
+    }
+
+    #[test]
+    fn daa_after_bcd_add_and_sub() {
+        let mut cpu = running(&[
+            instructions::LD_A_d8, 0x45,
+            instructions::ADD_A_d8, 0x38,
+            instructions::DAA,
+            instructions::SUB_d8, 0x38,
+            instructions::DAA,
+        ]);
+        cpu.run_n(3);
+        assert_eq!(cpu.registers.get8(Register8::A), 0x83);
+        assert!(!cpu.registers.get_flag(Flag::Carry));
+        cpu.run_n(2);
+        assert_eq!(cpu.registers.get8(Register8::A), 0x45);
+        assert!(cpu.registers.get_flag(Flag::Subtract));
+    }
+
+    #[test]
+    fn inc_and_dec_leave_carry() {
+        let mut cpu = running(&[
+            instructions::SCF,
+            instructions::LD_B_d8, 0x0F,
+            instructions::INC_B,
+            instructions::LD_C_d8, 0x01,
+            instructions::DEC_C,
+            instructions::DEC_C,
+        ]);
+        cpu.run_n(3);
+        assert_eq!(cpu.registers.get8(Register8::B), 0x10);
+        assert_eq!(flags(&cpu), 0x30);
+        cpu.run_n(2);
+        assert_eq!(cpu.registers.get8(Register8::C), 0x00);
+        assert_eq!(flags(&cpu), 0xD0);
+        cpu.run_n(1);
+        assert_eq!(cpu.registers.get8(Register8::C), 0xFF);
+        assert_eq!(flags(&cpu), 0x70);
+    }
+
+    #[test]
+    fn loads_through_memory() {
+        let mut cpu = running(&[
+            instructions::LD_A_d8, 0x42,
+            instructions::LD_a16_A, 0x23, 0xC1,
+            instructions::XOR_A,
+            instructions::LD_A_a16, 0x23, 0xC1,
+            instructions::LDH_a8_A, 0x90,
+            instructions::LD_C_d8, 0x90,
+            instructions::XOR_A,
+            instructions::LD_A_aC,
+            instructions::LD_HL_d16, 0x00, 0xC0,
+            instructions::LD_aHLp_A,
+            instructions::DEC_HL,
+            instructions::LD_B_aHL,
+        ]);
+        cpu.run_n(4);
+        assert_eq!(cpu.registers.get8(Register8::A), 0x42);
+        cpu.run_n(4);
+        assert_eq!(cpu.registers.get8(Register8::A), 0x42);
+        assert_eq!(cpu.memory.get8(0xFF90), 0x42);
+        cpu.run_n(2);
+        assert_eq!(cpu.registers.get16(Register16::HL), 0xC001);
+        cpu.run_n(2);
+        assert_eq!(cpu.registers.get8(Register8::B), 0x42);
+    }
+
+    #[test]
+    fn push_and_pop() {
+        let mut cpu = running(&[
+            instructions::LD_BC_d16, 0x34, 0x12,
+            instructions::PUSH_BC,
+            instructions::POP_AF,
+        ]);
+        cpu.run_n(2);
+        assert_eq!(cpu.registers.sp, 0xFFFC);
+        assert_eq!(cpu.memory.get8(0xFFFD), 0x12);
+        assert_eq!(cpu.memory.get8(0xFFFC), 0x34);
+        cpu.run_n(1);
+        assert_eq!(cpu.registers.sp, 0xFFFE);
+        // The low nibble of F doesn't exist
+        assert_eq!(cpu.registers.get8(Register8::A), 0x12);
+        assert_eq!(flags(&cpu), 0x30);
+    }
+
+    #[test]
+    fn jumps_calls_and_their_timing() {
+        let mut cpu = running(&[
+            instructions::XOR_A,
+            instructions::JR_NZ_s8, 0x10,
+            instructions::JP_Z_a16, 0x10, 0x01,
+        ]);
+        cpu.run_n(1);
+        assert_eq!(cpu.step(), 2);
+        assert_eq!(cpu.step(), 4);
+        assert_eq!(cpu.registers.pc, 0x0110);
+
+        let mut cpu = running(&[
+            instructions::CALL_a16, 0x10, 0x01,
+        ]);
+        assert_eq!(cpu.step(), 6);
+        assert_eq!(cpu.registers.pc, 0x0110);
+        assert_eq!(cpu.registers.sp, 0xFFFC);
+        assert_eq!(cpu.memory.get8(0xFFFC), 0x03);
+        assert_eq!(cpu.memory.get8(0xFFFD), 0x01);
+
+        let mut cpu = running(&[
+            instructions::RST_28H,
+        ]);
+        assert_eq!(cpu.step(), 4);
+        assert_eq!(cpu.registers.pc, 0x0028);
+        assert_eq!(cpu.memory.get8(0xFFFC), 0x01);
+
+        let mut cpu = running(&[
+            instructions::CALL_a16, 0x05, 0x01,
+            instructions::HALT,
+            instructions::NO_OP,
+            // 0x0105
+            instructions::SCF,
+            instructions::RET_NC,
+            instructions::RET_C,
+        ]);
+        cpu.run_n(2);
+        assert_eq!(cpu.step(), 2);
+        assert_eq!(cpu.step(), 5);
+        assert_eq!(cpu.registers.pc, 0x0103);
+        assert_eq!(cpu.registers.sp, 0xFFFE);
+    }
+
+    #[test]
+    fn prefixed_shifts_and_bits() {
+        let mut cpu = running(&[
+            instructions::LD_B_d8, 0x85,
+            instructions::PREFIX, 0x00, // RLC B
+            instructions::PREFIX, 0x28, // SRA B
+            instructions::LD_C_d8, 0x01,
+            instructions::PREFIX, 0x39, // SRL C
+            instructions::LD_D_d8, 0x00,
+            instructions::PREFIX, 0xFA, // SET 7,D
+            instructions::PREFIX, 0xBA, // RES 7,D
+        ]);
+        cpu.run_n(2);
+        assert_eq!(cpu.registers.get8(Register8::B), 0x0B);
+        assert_eq!(flags(&cpu), 0x10);
+        cpu.run_n(1);
+        assert_eq!(cpu.registers.get8(Register8::B), 0x05);
+        assert_eq!(flags(&cpu), 0x10);
+        cpu.run_n(2);
+        assert_eq!(cpu.registers.get8(Register8::C), 0x00);
+        assert_eq!(flags(&cpu), 0x90);
+        cpu.run_n(2);
+        assert_eq!(cpu.registers.get8(Register8::D), 0x80);
+        cpu.run_n(1);
+        assert_eq!(cpu.registers.get8(Register8::D), 0x00);
+    }
+
+    #[test]
+    fn rla_clears_zero() {
+        let mut cpu = running(&[
+            instructions::XOR_A,
+            instructions::SCF,
+            instructions::LD_A_d8, 0x80,
+            instructions::RLA,
+        ]);
+        cpu.run_n(4);
+        assert_eq!(cpu.registers.get8(Register8::A), 0x01);
+        assert_eq!(flags(&cpu), 0x10);
+    }
+
+    #[test]
+    fn sixteen_bit_adds() {
+        let mut cpu = running(&[
+            instructions::XOR_A,
+            instructions::LD_HL_d16, 0xFF, 0x0F,
+            instructions::LD_BC_d16, 0x01, 0x00,
+            instructions::ADD_HL_BC,
+            instructions::LD_SP_d16, 0xF8, 0xFF,
+            instructions::LD_HL_SP_ADD_s8, 0x08,
+            instructions::ADD_SP_s8, 0xF8,
+        ]);
+        cpu.run_n(4);
+        assert_eq!(cpu.registers.get16(Register16::HL), 0x1000);
+        // Z is left alone
+        assert_eq!(flags(&cpu), 0xA0);
+        cpu.run_n(2);
+        assert_eq!(cpu.registers.get16(Register16::HL), 0x0000);
+        assert_eq!(flags(&cpu), 0x30);
+        cpu.run_n(1);
+        assert_eq!(cpu.registers.sp, 0xFFF0);
+        assert_eq!(flags(&cpu), 0x30);
+    }
+
+    #[test]
+    fn interrupt_dispatch() {
+        let mut cpu = running(&[
+            instructions::LD_A_d8, 0x04,
+            instructions::LDH_a8_A, 0xFF,
+            instructions::LDH_a8_A, 0x0F,
+            instructions::EI,
+            instructions::NO_OP,
+        ]);
+        cpu.run_n(5);
+        assert_eq!(cpu.step(), 5);
+        assert_eq!(cpu.registers.pc, Interrupt::Timer.vector());
+        assert!(!cpu.ime);
+        assert_eq!(cpu.memory.get8(0xFF0F) & 0x04, 0);
+        assert_eq!(cpu.memory.get8(0xFFFC), 0x08);
+    }
+}
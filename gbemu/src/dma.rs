@@ -0,0 +1,121 @@
+// OAM DMA
+//
+// Writing XX to 0xFF46 copies XX00-XX9F into OAM, a byte per M-cycle, after an M-cycle of
+// setup. While a transfer runs DMA owns the bus: the CPU can still reach HRAM and the IO
+// registers, but reads anywhere else see the byte DMA is moving (OAM reads 0xFF) and writes
+// go nowhere, which is why games run their DMA routine from HRAM.
+//
+// Writing 0xFF46 mid-transfer starts over from the new source, but only once its setup is
+// done. Until then the old transfer carries on, bus and all.
+
+pub const DMA: u16 = 0xFF46;
+
+const OAM_START: u16 = 0xFE00;
+const LENGTH: u16 = 0xA0;
+const SETUP_CYCLES: u8 = 1;
+
+pub struct Dma {
+    register: u8,
+    source: u16,
+    // Next byte to copy, LENGTH when idle
+    index: u16,
+    // Source page of a transfer being set up, and the M-cycles before it starts
+    pending: Option<(u8, u8)>,
+    // Byte on the bus as of the last copy
+    moving: u8,
+}
+
+impl Dma {
+    pub fn new() -> Self {
+        Self { register: 0, source: 0, index: LENGTH, pending: None, moving: 0xFF }
+    }
+
+    pub fn read(&self) -> u8 {
+        self.register
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.register = value;
+        self.pending = Some((value, SETUP_CYCLES));
+    }
+
+    // Set the register as the boot ROM left it, without starting a transfer
+    pub fn restore(&mut self, value: u8) {
+        self.register = value;
+    }
+
+    // Whether the CPU is locked out of everything below 0xFF00
+    pub fn active(&self) -> bool {
+        self.index < LENGTH
+    }
+
+    // What a CPU read outside HRAM and IO sees during a transfer
+    pub fn conflict(&self, addr: u16) -> u8 {
+        if (OAM_START..OAM_START + LENGTH).contains(&addr) { 0xFF } else { self.moving }
+    }
+
+    // Record the byte just copied, which the CPU sees on the bus
+    pub fn moved(&mut self, value: u8) {
+        self.moving = value;
+    }
+
+    // Advance one M-cycle, returning the source and OAM address to copy a byte between if any
+    pub fn step(&mut self) -> Option<(u16, u16)> {
+        let copy = if self.active() {
+            let copy = (self.source + self.index, OAM_START + self.index);
+            self.index += 1;
+            Some(copy)
+        }
+        else {
+            None
+        };
+
+        if let Some((page, cycles)) = self.pending {
+            if cycles > 1 {
+                self.pending = Some((page, cycles - 1));
+            }
+            else {
+                self.pending = None;
+                self.source = (page as u16) << 8;
+                self.index = 0;
+            }
+        }
+        copy
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_after_setup() {
+        let mut dma = Dma::new();
+        dma.write(0xC1);
+        assert_eq!(dma.read(), 0xC1);
+        assert!(!dma.active());
+        assert_eq!(dma.step(), None);
+        assert!(dma.active());
+
+        let copies: Vec<_> = (0..LENGTH).map(|_| dma.step().unwrap()).collect();
+        assert_eq!(copies[0], (0xC100, 0xFE00));
+        assert_eq!(copies[0x9F], (0xC19F, 0xFE9F));
+        assert!(!dma.active());
+        assert_eq!(dma.step(), None);
+    }
+
+    #[test]
+    fn restart_carries_on_until_set_up() {
+        let mut dma = Dma::new();
+        dma.write(0xC0);
+        for _ in 0..11 {
+            dma.step();
+        }
+        dma.write(0xD0);
+        assert_eq!(dma.step(), Some((0xC00A, 0xFE0A)));
+        assert_eq!(dma.step(), Some((0xD000, 0xFE00)));
+        assert_eq!((1..LENGTH).filter_map(|_| dma.step()).count(), LENGTH as usize - 1);
+        assert!(!dma.active());
+    }
+}
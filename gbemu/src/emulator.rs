@@ -0,0 +1,575 @@
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+use wasm_bindgen::prelude::*;
+
+use crate::boot_rom::free_boot_rom;
+use crate::cartridge::{Cartridge, MapperKind};
+use crate::cpu::LR35902;
+use crate::init::InitPolicy;
+use crate::joypad::{Button, ButtonState};
+use crate::serial::SerialDevice;
+use crate::model::Model;
+use crate::palette::Palettes;
+use crate::png;
+use crate::ppu::{HEIGHT, WIDTH};
+use crate::ppu::{LockedAccess, LockedAccessPolicy, Renderer};
+
+const DMG_BOOT_ROM_SIZE: usize = 0x100;
+const CGB_BOOT_ROM_SIZE: usize = 0x900;
+// 154 lines of 114 M-cycles
+const FRAME_CYCLES: u64 = 154 * 114;
+// Screenshots are 160x144 pixels times this squared at most, about 5.9 MB of RGBA
+pub const MAX_SCREENSHOT_SCALE: u32 = 16;
+
+// The emulator as seen from outside the crate, and from JS
+#[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), wasm_bindgen)]
+pub struct Emulator {
+    cpu: LR35902,
+    palettes: Palettes,
+    // The picture in the palettes' colours as of the last run_frame
+    rgba: Vec<u8>,
+}
+
+#[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), wasm_bindgen)]
+impl Emulator {
+    #[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), wasm_bindgen(constructor))]
+    // Fails on cartridge types without a mapper, see with_mapper
+    pub fn new(rom: Vec<u8>, model: Model) -> Result<Emulator, String> {
+        Ok(Self::from_cpu(LR35902::open(rom, model)?))
+    }
+
+    // Start from power on, running a user supplied DMG (256 byte) or CGB (2304 byte) boot ROM
+    pub fn with_boot_rom(rom: Vec<u8>, boot_rom: Vec<u8>, model: Model) -> Result<Emulator, String> {
+        if boot_rom.len() != DMG_BOOT_ROM_SIZE && boot_rom.len() != CGB_BOOT_ROM_SIZE {
+            return Err(format!(
+                "Boot ROM must be {DMG_BOOT_ROM_SIZE} (DMG) or {CGB_BOOT_ROM_SIZE} (CGB) bytes, got {}",
+                boot_rom.len()
+            ));
+        }
+        Ok(Self::from_cpu(LR35902::boot(Cartridge::new(rom)?, boot_rom, model)))
+    }
+
+    // Start from power on, running the built-in boot ROM
+    pub fn with_free_boot_rom(rom: Vec<u8>, model: Model) -> Result<Emulator, String> {
+        let mut cpu = LR35902::boot(Cartridge::new(rom)?, free_boot_rom(model), model);
+        cpu.memory.set_post_boot_io_at_handover();
+        Ok(Self::from_cpu(cpu))
+    }
+
+    // Power on with RAM, and registers if booting, filled according to the policy rather than
+    // zeroed. Only meaningful before anything has run.
+    pub fn with_init_policy(mut self, policy: InitPolicy) -> Self {
+        self.cpu.initialise(&policy);
+        self
+    }
+
+    // Draw with the pixel FIFO for accurate mode 3 timing and mid-line effects, or a line at a
+    // time, which is cheaper
+    pub fn with_renderer(mut self, renderer: Renderer) -> Self {
+        self.cpu.memory.set_renderer(renderer);
+        self
+    }
+
+    // Log or break on the CPU reaching for VRAM or OAM while the PPU has it locked
+    pub fn with_locked_access_policy(mut self, policy: LockedAccessPolicy) -> Self {
+        self.cpu.memory.set_locked_access_policy(policy);
+        self
+    }
+
+    // Colours to draw each layer's shades in
+    pub fn with_palettes(mut self, palettes: Palettes) -> Self {
+        self.set_palettes(palettes);
+        self
+    }
+
+    pub fn set_palettes(&mut self, palettes: Palettes) {
+        self.palettes = palettes;
+        self.palettes.convert(self.cpu.memory.framebuffer(), &mut self.rgba);
+    }
+
+    pub fn palettes(&self) -> Palettes {
+        self.palettes
+    }
+
+    // The picture as last drawn in the palettes' colours, 160x144 pixels of 4 bytes each
+    pub fn framebuffer_rgba(&self) -> Vec<u8> {
+        let framebuffer = self.cpu.memory.framebuffer();
+        let mut rgba = vec![0; framebuffer.len() * 4];
+        self.palettes.convert(framebuffer, &mut rgba);
+        rgba
+    }
+
+    // Run to the start of the next VBlank, then draw the picture for framebuffer_ptr. Gives up
+    // after a frame's worth of time, as with the LCD off or the CPU stopped there's no VBlank
+    // to wait for, and stops early on breaking for a locked VRAM or OAM access.
+    pub fn run_frame(&mut self) {
+        let frame = self.cpu.memory.frames();
+        let end = self.cpu.cycle + FRAME_CYCLES;
+        while self.cpu.memory.frames() == frame && self.cpu.cycle < end && self.cpu.memory.locked_access().is_none() {
+            self.cpu.step();
+        }
+        self.palettes.convert(self.cpu.memory.framebuffer(), &mut self.rgba);
+    }
+
+    // The picture as last drawn in the palettes' colours, encoded as a PNG with each pixel
+    // blown up to scale x scale, from 1 to MAX_SCREENSHOT_SCALE
+    pub fn screenshot_png(&self, scale: u32) -> Result<Vec<u8>, String> {
+        let (width, height, rgba) = self.screenshot(scale)?;
+        Ok(png::encode_rgba(width, height, &rgba))
+    }
+
+    // Where the RGBA picture drawn by run_frame is in wasm memory, for an ImageData over it.
+    // Stays put for the emulator's lifetime.
+    pub fn framebuffer_ptr(&self) -> *const u8 {
+        self.rgba.as_ptr()
+    }
+
+    pub fn framebuffer_len(&self) -> usize {
+        self.rgba.len()
+    }
+
+    // The policy RAM was filled with, including the seed needed to reproduce it
+    pub fn init_policy(&self) -> InitPolicy {
+        self.cpu.memory.init_policy()
+    }
+
+    // Whether the game has ever switched the LCD off outside VBlank, which real hardware
+    // doesn't take kindly to
+    pub fn unsafe_lcd_off(&self) -> bool {
+        self.cpu.memory.unsafe_lcd_off()
+    }
+
+    // Whether the boot ROM is still mapped, i.e. it hasn't yet handed over to the cartridge
+    pub fn in_boot_rom(&self) -> bool {
+        self.cpu.memory.boot_rom_mapped()
+    }
+
+    // Skip mapper detection, for carts whose headers give nothing away
+    pub fn with_mapper(rom: Vec<u8>, kind: MapperKind, model: Model) -> Result<Emulator, String> {
+        Ok(Self::insert(Cartridge::with_mapper(rom, kind)?, model))
+    }
+
+    pub fn model(&self) -> Model {
+        self.cpu.memory.model()
+    }
+
+    pub fn mapper(&self) -> MapperKind {
+        self.cpu.memory.cartridge().kind
+    }
+
+    pub fn rumble(&self) -> u8 {
+        self.cpu.memory.cartridge().rumble()
+    }
+
+    // Whether the cartridge keeps its RAM when switched off, i.e. whether saving it is useful
+    pub fn has_battery(&self) -> bool {
+        self.cpu.memory.cartridge().has_battery()
+    }
+
+    pub fn set_buttons(&mut self, buttons: ButtonState) {
+        self.cpu.memory.set_buttons(buttons);
+    }
+
+    pub fn press(&mut self, button: Button) {
+        self.set_button(button, true);
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.set_button(button, false);
+    }
+
+    // Restore cartridge RAM from a raw .sav file
+    pub fn load_save_ram(&mut self, data: &[u8]) {
+        self.cpu.memory.cartridge_mut().load_ram(data);
+    }
+
+    // Export cartridge RAM as a raw .sav file. Clears the dirty flag.
+    pub fn save_ram(&mut self) -> Vec<u8> {
+        let cartridge = self.cpu.memory.cartridge_mut();
+        cartridge.clear_ram_dirty();
+        cartridge.ram().to_vec()
+    }
+
+    // Whether cartridge RAM has changed since it was last exported or loaded
+    pub fn save_ram_dirty(&self) -> bool {
+        self.cpu.memory.cartridge().ram_dirty()
+    }
+}
+
+impl Emulator {
+    fn set_button(&mut self, button: Button, pressed: bool) {
+        let mut buttons = self.cpu.memory.buttons();
+        buttons.set(button, pressed);
+        self.set_buttons(buttons);
+    }
+
+    fn from_cpu(cpu: LR35902) -> Self {
+        let mut emulator = Self { cpu, palettes: Palettes::default(), rgba: vec![0; WIDTH * HEIGHT * 4] };
+        emulator.palettes.convert(emulator.cpu.memory.framebuffer(), &mut emulator.rgba);
+        emulator
+    }
+
+    pub fn insert(cartridge: Cartridge, model: Model) -> Self {
+        Self::from_cpu(LR35902::insert(cartridge, model))
+    }
+
+    // Plug something into the link port, replacing whatever was there
+    pub fn connect_serial(&mut self, device: Box<dyn SerialDevice>) {
+        self.cpu.memory.connect_serial(device);
+    }
+
+    pub fn run(&mut self) {
+        self.cpu.run();
+    }
+
+    // Run a single instruction, returning the M-cycles it took
+    pub fn step(&mut self) -> u64 {
+        self.cpu.step()
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.cpu.registers.pc
+    }
+
+    // M-cycles run since power on
+    pub fn cycles(&self) -> u64 {
+        self.cpu.cycle
+    }
+
+    // The picture as last drawn, 160x144 pixels row by row from the top, each a colour number
+    // with the palette layer above it
+    pub fn framebuffer(&self) -> &[u8] {
+        self.cpu.memory.framebuffer()
+    }
+
+    // The first VRAM or OAM access blocked since last called, when breaking on them. Running
+    // won't carry on past it until it's taken.
+    pub fn take_locked_access(&mut self) -> Option<LockedAccess> {
+        self.cpu.memory.take_locked_access()
+    }
+
+    // The screenshot's size and RGBA pixels
+    fn screenshot(&self, scale: u32) -> Result<(usize, usize, Vec<u8>), String> {
+        if !(1..=MAX_SCREENSHOT_SCALE).contains(&scale) {
+            return Err(format!("Screenshot scale must be 1 to {MAX_SCREENSHOT_SCALE}, got {scale}"));
+        }
+        let scale = scale as usize;
+        Ok((WIDTH * scale, HEIGHT * scale, png::upscale(WIDTH, &self.framebuffer_rgba(), scale)))
+    }
+
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn save_screenshot(&self, path: impl AsRef<std::path::Path>, scale: u32) -> std::io::Result<()> {
+        let (width, height, rgba) = self.screenshot(scale).map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidInput, error))?;
+        png::write(path, width, height, &rgba)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn cpu(&self) -> &LR35902 {
+        &self.cpu
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::NINTENDO_LOGO;
+    use crate::init::InitPattern;
+    use crate::palette::{Palette, PalettePreset};
+    use crate::ppu::Mode;
+    use crate::registers::Registers;
+
+    fn battery_rom() -> Vec<u8> {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0147] = 0x09; // ROM+RAM+BATTERY
+        rom[0x0149] = 0x02; // 8KiB
+        rom[0x014D] = 0xDC; // header checksum
+        rom
+    }
+
+    #[test]
+    fn boot_rom_hands_over_at_0x0100() {
+        // LD SP,0xFFFE; LD A,0x01; LD HL,0xFF50; NOPs...; LD (HL),A at 0x00FF
+        let mut boot_rom = vec![0x31, 0xFE, 0xFF, 0x3E, 0x01, 0x21, 0x50, 0xFF];
+        boot_rom.resize(0x100, 0x00);
+        boot_rom[0xFF] = 0x77;
+
+        let mut rom = battery_rom();
+        rom[0x0000] = 0xAB;
+
+        let mut emulator = Emulator::with_boot_rom(rom, boot_rom, Model::Dmg).unwrap();
+        assert_eq!(emulator.pc(), 0x0000);
+        assert_eq!(emulator.cpu.memory.get8(0x0000), 0x31);
+
+        while emulator.in_boot_rom() {
+            emulator.step();
+        }
+        assert_eq!(emulator.pc(), 0x0100);
+        assert_eq!(emulator.cpu.memory.get8(0x0000), 0xAB);
+
+        assert!(Emulator::with_boot_rom(battery_rom(), vec![0; 10], Model::Dmg).is_err());
+    }
+
+    #[test]
+    fn free_boot_rom_leaves_post_boot_state() {
+        let mut rom = battery_rom();
+        rom[0x0104..0x0134].copy_from_slice(&NINTENDO_LOGO);
+
+        for model in [Model::Dmg0, Model::Dmg, Model::Mgb, Model::Sgb, Model::Sgb2, Model::Cgb, Model::Agb] {
+            let mut emulator = Emulator::with_free_boot_rom(rom.clone(), model).unwrap();
+            let mut cycles = 0;
+            while emulator.in_boot_rom() {
+                cycles += emulator.step();
+            }
+            // About 100 frames of scrolling and 60 holding
+            assert!((160 * 17_000..160 * 18_000).contains(&cycles), "{}", cycles);
+
+            let registers = &emulator.cpu.registers;
+            let expected = Registers::post_boot(model, &emulator.cpu.memory.cartridge().header);
+            assert_eq!(registers.pc, 0x0100);
+            assert_eq!(registers.sp, expected.sp);
+            assert_eq!(registers.af.get16(), expected.af.get16(), "{model:?}");
+            assert_eq!(registers.bc.get16(), expected.bc.get16(), "{model:?}");
+            assert_eq!(registers.de.get16(), expected.de.get16(), "{model:?}");
+            assert_eq!(registers.hl.get16(), expected.hl.get16(), "{model:?}");
+
+            // IO as if the boot ROM had been skipped
+            let mut skipped = Emulator::new(rom.clone(), model).unwrap();
+            let memory = &mut emulator.cpu.memory;
+            for (addr, _) in model.post_boot_io() {
+                assert_eq!(memory.get8(addr), skipped.cpu.memory.get8(addr), "{:?} {:#06x}", model, addr);
+            }
+            // Top row of the "N", 0xCE doubled up
+            assert_eq!(memory.get8(0x8010), 0xF0);
+            assert_eq!(memory.get8(0x8012), 0xF0);
+            assert_eq!(memory.get8(0x9904), 0x01);
+            assert_eq!(memory.get8(0x992F), 0x18);
+        }
+    }
+
+    #[test]
+    fn unsupported_cartridge_type() {
+        let mut rom = battery_rom();
+        rom[0x0147] = 0x01;
+        let Err(error) = Emulator::new(rom.clone(), Model::Dmg) else {
+            panic!("MBC1 cartridge accepted");
+        };
+        assert_eq!(error, "Unsupported cartridge type 0x01 (MBC1)");
+        assert_eq!(Emulator::with_mapper(rom.clone(), MapperKind::RomOnly, Model::Dmg).unwrap().mapper(), MapperKind::RomOnly);
+        let Err(error) = Emulator::with_mapper(rom, MapperKind::Tpp1, Model::Dmg) else {
+            panic!("TPP1 forced without its magic");
+        };
+        assert_eq!(error, "TPP1 mapper selected but the header has no TPP1 magic");
+    }
+
+    #[test]
+    fn model_sets_post_boot_state() {
+        let dmg = Emulator::new(battery_rom(), Model::Dmg).unwrap();
+        assert_eq!(dmg.cpu.registers.af.get16(), 0x01B0);
+        let mgb = Emulator::new(battery_rom(), Model::Mgb).unwrap();
+        assert_eq!(mgb.cpu.registers.af.get16(), 0xFFB0);
+        let agb = Emulator::new(battery_rom(), Model::Agb).unwrap();
+        assert_eq!(agb.cpu.registers.af.get16(), 0x1100);
+        assert_eq!(agb.cpu.registers.bc.get16(), 0x0100);
+
+        // A zero checksum clears H and C
+        let mut rom = battery_rom();
+        rom[0x0134] = 0xDC;
+        rom[0x014D] = 0x00;
+        let dmg = Emulator::new(rom, Model::Dmg).unwrap();
+        assert_eq!(dmg.cpu.registers.af.get16(), 0x0180);
+
+        // CGB only registers are absent on the DMG
+        let mut dmg = Emulator::new(battery_rom(), Model::Dmg).unwrap();
+        dmg.cpu.memory.set8(0xFF70, 0x02);
+        assert_eq!(dmg.cpu.memory.get8(0xFF70), 0xFF);
+        let mut cgb = Emulator::new(battery_rom(), Model::Cgb).unwrap();
+        assert_eq!(cgb.cpu.memory.get8(0xFF70), 0xF8);
+        cgb.cpu.memory.set8(0xFF70, 0x02);
+        assert_eq!(cgb.cpu.memory.get8(0xFF70), 0x02);
+
+        // SC's fast clock bit only exists on the CGB
+        assert_eq!(dmg.cpu.memory.get8(0xFF02), 0x7E);
+        assert_eq!(cgb.cpu.memory.get8(0xFF02), 0x7F);
+        let mut agb = Emulator::new(battery_rom(), Model::Agb).unwrap();
+        assert_eq!(agb.cpu.memory.get8(0xFF02), 0x7F);
+    }
+
+    #[test]
+    fn init_policy_is_reproducible() {
+        let policy = InitPolicy::new(InitPattern::Random, 1234);
+        let mut a = Emulator::new(battery_rom(), Model::Dmg).unwrap().with_init_policy(policy);
+        let mut b = Emulator::new(battery_rom(), Model::Dmg).unwrap().with_init_policy(a.init_policy());
+        let wram = |emulator: &mut Emulator| (0xC000..0xC100).map(|addr| emulator.cpu.memory.get8(addr)).collect::<Vec<_>>();
+        assert_eq!(wram(&mut a), wram(&mut b));
+        assert!(wram(&mut a).iter().any(|b| *b != 0));
+
+        let mut ones = Emulator::new(battery_rom(), Model::Dmg).unwrap().with_init_policy(InitPolicy::new(InitPattern::Ones, 0));
+        assert_eq!(ones.cpu.memory.get8(0xFF80), 0xFF);
+        assert_eq!(ones.cpu.memory.get8(0x8000), 0xFF);
+        // Registers are left alone without a boot ROM
+        assert_eq!(ones.cpu.registers.af.get16(), 0x01B0);
+
+        let booting = Emulator::with_boot_rom(battery_rom(), vec![0; 0x100], Model::Dmg)
+            .unwrap()
+            .with_init_policy(InitPolicy::new(InitPattern::Ones, 0));
+        assert_eq!(booting.cpu.registers.bc.get16(), 0xFFFF);
+    }
+
+    #[test]
+    fn joypad_selects_interrupts_and_wakes_from_stop() {
+        // Select the buttons, enable the joypad interrupt, STOP
+        let mut rom = battery_rom();
+        rom[0x0100..0x010A].copy_from_slice(&[0x3E, 0x10, 0xE0, 0x00, 0x3E, 0x10, 0xE0, 0xFF, 0x10, 0x00]);
+        let mut emulator = Emulator::new(rom, Model::Dmg).unwrap();
+        while !emulator.cpu.stopped {
+            emulator.step();
+        }
+        let pc = emulator.pc();
+        emulator.step();
+        assert_eq!(emulator.pc(), pc);
+
+        // Directions aren't selected
+        emulator.press(Button::Up);
+        emulator.step();
+        assert!(emulator.cpu.stopped);
+        assert_eq!(emulator.cpu.memory.get8(0xFF00), 0xDF);
+
+        emulator.press(Button::Start);
+        assert_eq!(emulator.cpu.memory.get8(0xFF00), 0xD7);
+        assert_eq!(emulator.cpu.memory.pending_interrupts(), 0x10);
+        emulator.step();
+        assert!(!emulator.cpu.stopped);
+
+        emulator.release(Button::Start);
+        assert_eq!(emulator.cpu.memory.get8(0xFF00), 0xDF);
+    }
+
+    #[test]
+    fn oam_dma_from_hram() {
+        let mut rom = battery_rom();
+        let mut program = vec![
+            0xAF,             // XOR A
+            0xE0, 0x40,       // LDH ($40),A, so OAM is never locked
+            0x21, 0x00, 0xC0, // LD HL,$C000
+            0x7D,             // LD A,L
+            0x22,             // LD (HL+),A
+            0x7D,             // LD A,L
+            0xFE, 0xA0,       // CP $A0
+            0x20, 0xF9,       // JR NZ,-7
+        ];
+        // LDH ($46),A; LD A,40; DEC A; JR NZ,-3; RET copied to 0xFF80
+        let routine = [0xE0, 0x46, 0x3E, 0x28, 0x3D, 0x20, 0xFD, 0xC9];
+        for (i, byte) in routine.iter().enumerate() {
+            program.extend([0x3E, *byte, 0xE0, 0x80 + i as u8]);
+        }
+        program.extend([0x3E, 0xC0, 0xCD, 0x80, 0xFF, 0x76]); // LD A,$C0; CALL $FF80; HALT
+        rom[0x0100..0x0100 + program.len()].copy_from_slice(&program);
+
+        let mut emulator = Emulator::new(rom, Model::Dmg).unwrap();
+        while !emulator.cpu.halted && emulator.cycles() < 10_000 {
+            emulator.step();
+        }
+        let memory = &mut emulator.cpu.memory;
+        assert_eq!(memory.get8(0xFE05), 0x05);
+        assert_eq!(memory.get8(0xFE9F), 0x9F);
+
+        // Mid-transfer only HRAM and IO can be reached
+        memory.set8(0xFF46, 0xC0);
+        memory.tick(4);
+        assert_eq!(memory.get8(0x0150), 0x02);
+        assert_eq!(memory.get8(0xFE00), 0xFF);
+        assert_eq!(memory.get8(0xFF80), 0xE0);
+        memory.set8(0xC000, 0x55);
+        memory.tick(160);
+        assert_eq!(memory.get8(0xC000), 0x00);
+    }
+
+    #[test]
+    fn locked_vram_and_oam() {
+        let mut emulator = Emulator::new(battery_rom(), Model::Dmg).unwrap().with_locked_access_policy(LockedAccessPolicy::Break);
+        let memory = &mut emulator.cpu.memory;
+        while memory.get8(0xFF41) & 0x03 != 2 {
+            memory.tick(1);
+        }
+        memory.set8(0x8000, 0x12);
+        memory.set8(0xFE00, 0x34);
+        assert_eq!(memory.get8(0xFE00), 0xFF);
+        assert_eq!(memory.take_locked_access(), Some(LockedAccess { addr: 0xFE00, value: Some(0x34), mode: Mode::OamScan }));
+
+        while memory.get8(0xFF41) & 0x03 != 3 {
+            memory.tick(1);
+        }
+        assert_eq!(memory.get8(0x8000), 0xFF);
+        memory.set8(0x8001, 0x56);
+        assert_eq!(memory.take_locked_access(), Some(LockedAccess { addr: 0x8000, value: None, mode: Mode::Drawing }));
+
+        while memory.get8(0xFF41) & 0x03 != 0 {
+            memory.tick(1);
+        }
+        assert_eq!(memory.get8(0x8000), 0x12);
+        assert_eq!(memory.get8(0x8001), 0x00);
+        assert_eq!(memory.get8(0xFE00), 0x00);
+        assert_eq!(memory.take_locked_access(), None);
+    }
+
+    #[test]
+    fn run_frame_stops_at_vblank() {
+        let mut rom = battery_rom();
+        rom[0x0100..0x0102].copy_from_slice(&[0x18, 0xFE]); // JR -2
+        let mut emulator = Emulator::new(rom, Model::Dmg).unwrap().with_palettes(Palettes::uniform(Palette::preset(PalettePreset::HighContrast)));
+        assert_eq!(emulator.framebuffer_len(), 160 * 144 * 4);
+
+        emulator.run_frame();
+        let start = emulator.cycles();
+        emulator.run_frame();
+        assert_eq!(emulator.cpu.memory.frames(), 2);
+        assert!((FRAME_CYCLES..FRAME_CYCLES + 4).contains(&(emulator.cycles() - start)));
+        let rgba = unsafe { std::slice::from_raw_parts(emulator.framebuffer_ptr(), emulator.framebuffer_len()) };
+        assert_eq!(rgba[..4], [0xFF, 0xFF, 0xFF, 0xFF]);
+
+        // No VBlank with the LCD off, but the frame still ends
+        emulator.cpu.memory.set8(0xFF40, 0x00);
+        emulator.run_frame();
+        assert_eq!(emulator.cpu.memory.frames(), 2);
+    }
+
+    #[test]
+    fn screenshots() {
+        let emulator = Emulator::new(battery_rom(), Model::Dmg).unwrap();
+        let png = emulator.screenshot_png(3).unwrap();
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(png[16..24], [0, 0, 1, 0xE0, 0, 0, 1, 0xB0]);
+        assert!(emulator.screenshot_png(0).is_err());
+        assert!(emulator.screenshot_png(MAX_SCREENSHOT_SCALE + 1).is_err());
+        assert!(emulator.screenshot_png(u32::MAX).is_err());
+
+        #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+        {
+            let path = std::env::temp_dir().join(format!("gbemu-screenshot-{}.png", std::process::id()));
+            emulator.save_screenshot(&path, 1).unwrap();
+            assert_eq!(std::fs::read(&path).unwrap(), emulator.screenshot_png(1).unwrap());
+            assert!(emulator.save_screenshot(&path, u32::MAX).is_err());
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn save_ram_round_trip() {
+        let mut emulator = Emulator::new(battery_rom(), Model::Dmg).unwrap();
+        assert!(emulator.has_battery());
+        assert!(!emulator.save_ram_dirty());
+
+        emulator.cpu.memory.set8(0xA010, 0x5A);
+        assert!(emulator.save_ram_dirty());
+
+        let save = emulator.save_ram();
+        assert!(!emulator.save_ram_dirty());
+        assert_eq!(save.len(), 0x2000);
+        assert_eq!(save[0x10], 0x5A);
+
+        let mut other = Emulator::new(battery_rom(), Model::Dmg).unwrap();
+        other.load_save_ram(&save);
+        assert_eq!(other.cpu.memory.get8(0xA010), 0x5A);
+        assert!(!other.save_ram_dirty());
+    }
+}
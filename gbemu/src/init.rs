@@ -0,0 +1,81 @@
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+use wasm_bindgen::prelude::*;
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::collections::hash_map::RandomState;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+use std::hash::{BuildHasher, Hasher};
+
+// What RAM holds at power on. Real consoles come up with semi-random contents, which is what
+// exposes reads of uninitialised memory.
+#[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), wasm_bindgen)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InitPattern {
+    Zeros,
+    Ones,
+    Random,
+}
+
+#[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), wasm_bindgen)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InitPolicy {
+    pub pattern: InitPattern,
+    // Keep this to reproduce a run
+    pub seed: u64,
+}
+
+#[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), wasm_bindgen)]
+impl InitPolicy {
+    #[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), wasm_bindgen(constructor))]
+    pub fn new(pattern: InitPattern, seed: u64) -> Self {
+        Self { pattern, seed }
+    }
+}
+
+// There is no entropy on wasm, so pass a seed in from JS there instead
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl InitPolicy {
+    // Pick a fresh seed
+    pub fn unseeded(pattern: InitPattern) -> Self {
+        let seed = RandomState::new().build_hasher().finish();
+        Self { pattern, seed }
+    }
+}
+
+impl Default for InitPolicy {
+    fn default() -> Self {
+        Self { pattern: InitPattern::Zeros, seed: 0 }
+    }
+}
+
+impl InitPolicy {
+    pub fn byte(&self, rng: &mut Rng) -> u8 {
+        match self.pattern {
+            InitPattern::Zeros => 0x00,
+            InitPattern::Ones => 0xFF,
+            InitPattern::Random => rng.next_u8(),
+        }
+    }
+}
+
+// xorshift64*, small and reproducible across platforms
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // Zero is a fixed point, so mix the seed first
+        const MIX: u64 = 0x9E37_79B9_7F4A_7C15;
+        Self(if seed == MIX { MIX } else { seed ^ MIX })
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+}
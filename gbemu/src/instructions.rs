@@ -149,26 +149,7 @@ pub const LD_A_A: u8 = 0x7F;
 
 
 
-// // 0xY*
-// pub const XX: u8 = 0xY0;
-// pub const XX: u8 = 0xY1;
-// pub const XX: u8 = 0xY2;
-// pub const XX: u8 = 0xY3;
-// pub const XX: u8 = 0xY4;
-// pub const XX: u8 = 0xY5;
-// pub const XX: u8 = 0xY6;
-// pub const XX: u8 = 0xY7;
-// pub const XX: u8 = 0xY8;
-// pub const XX: u8 = 0xY9;
-// pub const XX: u8 = 0xYA;
-// pub const XX: u8 = 0xYB;
-// pub const XX: u8 = 0xYC;
-// pub const XX: u8 = 0xYD;
-// pub const XX: u8 = 0xYE;
-// pub const XX: u8 = 0xYF;
-
-// TODO ...
-
+// 0x8*
 /*
     0x80 ADD A, B
     B1 C1
@@ -185,11 +166,129 @@ pub const ADD_A_B: u8 = 0x80;
 */
 pub const ADD_A_C: u8 = 0x81;
 
-// TODO ...
+pub const ADD_A_D: u8 = 0x82;
+pub const ADD_A_E: u8 = 0x83;
+pub const ADD_A_H: u8 = 0x84;
+pub const ADD_A_L: u8 = 0x85;
+pub const ADD_A_aHL: u8 = 0x86;
+pub const ADD_A_A: u8 = 0x87;
+pub const ADC_A_B: u8 = 0x88;
+pub const ADC_A_C: u8 = 0x89;
+pub const ADC_A_D: u8 = 0x8A;
+pub const ADC_A_E: u8 = 0x8B;
+pub const ADC_A_H: u8 = 0x8C;
+pub const ADC_A_L: u8 = 0x8D;
+pub const ADC_A_aHL: u8 = 0x8E;
+pub const ADC_A_A: u8 = 0x8F;
+
+// 0x9*
+pub const SUB_B: u8 = 0x90;
+pub const SUB_C: u8 = 0x91;
+pub const SUB_D: u8 = 0x92;
+pub const SUB_E: u8 = 0x93;
+pub const SUB_H: u8 = 0x94;
+pub const SUB_L: u8 = 0x95;
+pub const SUB_aHL: u8 = 0x96;
+pub const SUB_A: u8 = 0x97;
+pub const SBC_A_B: u8 = 0x98;
+pub const SBC_A_C: u8 = 0x99;
+pub const SBC_A_D: u8 = 0x9A;
+pub const SBC_A_E: u8 = 0x9B;
+pub const SBC_A_H: u8 = 0x9C;
+pub const SBC_A_L: u8 = 0x9D;
+pub const SBC_A_aHL: u8 = 0x9E;
+pub const SBC_A_A: u8 = 0x9F;
+
+// 0xA*
+pub const AND_B: u8 = 0xA0;
+pub const AND_C: u8 = 0xA1;
+pub const AND_D: u8 = 0xA2;
+pub const AND_E: u8 = 0xA3;
+pub const AND_H: u8 = 0xA4;
+pub const AND_L: u8 = 0xA5;
+pub const AND_aHL: u8 = 0xA6;
+pub const AND_A: u8 = 0xA7;
+pub const XOR_B: u8 = 0xA8;
+pub const XOR_C: u8 = 0xA9;
+pub const XOR_D: u8 = 0xAA;
+pub const XOR_E: u8 = 0xAB;
+pub const XOR_H: u8 = 0xAC;
+pub const XOR_L: u8 = 0xAD;
+pub const XOR_aHL: u8 = 0xAE;
+pub const XOR_A: u8 = 0xAF;
+
+// 0xB*
+pub const OR_B: u8 = 0xB0;
+pub const OR_C: u8 = 0xB1;
+pub const OR_D: u8 = 0xB2;
+pub const OR_E: u8 = 0xB3;
+pub const OR_H: u8 = 0xB4;
+pub const OR_L: u8 = 0xB5;
+pub const OR_aHL: u8 = 0xB6;
+pub const OR_A: u8 = 0xB7;
+pub const CP_B: u8 = 0xB8;
+pub const CP_C: u8 = 0xB9;
+pub const CP_D: u8 = 0xBA;
+pub const CP_E: u8 = 0xBB;
+pub const CP_H: u8 = 0xBC;
+pub const CP_L: u8 = 0xBD;
+pub const CP_aHL: u8 = 0xBE;
+pub const CP_A: u8 = 0xBF;
 
+// 0xC*
+pub const RET_NZ: u8 = 0xC0;
+pub const POP_BC: u8 = 0xC1;
+pub const JP_NZ_a16: u8 = 0xC2;
+pub const JP_a16: u8 = 0xC3;
+pub const CALL_NZ_a16: u8 = 0xC4;
+pub const PUSH_BC: u8 = 0xC5;
+pub const ADD_A_d8: u8 = 0xC6;
+pub const RST_00H: u8 = 0xC7;
+pub const RET_Z: u8 = 0xC8;
+pub const RET: u8 = 0xC9;
+pub const JP_Z_a16: u8 = 0xCA;
 pub const PREFIX: u8 = 0xCB;
+pub const CALL_Z_a16: u8 = 0xCC;
+pub const CALL_a16: u8 = 0xCD;
+pub const ADC_A_d8: u8 = 0xCE;
+pub const RST_08H: u8 = 0xCF;
 
-// TODO ...
+// 0xD*
+pub const RET_NC: u8 = 0xD0;
+pub const POP_DE: u8 = 0xD1;
+pub const JP_NC_a16: u8 = 0xD2;
+pub const CALL_NC_a16: u8 = 0xD4;
+pub const PUSH_DE: u8 = 0xD5;
+pub const SUB_d8: u8 = 0xD6;
+pub const RST_10H: u8 = 0xD7;
+pub const RET_C: u8 = 0xD8;
+pub const RETI: u8 = 0xD9;
+pub const JP_C_a16: u8 = 0xDA;
+pub const CALL_C_a16: u8 = 0xDC;
+pub const SBC_A_d8: u8 = 0xDE;
+pub const RST_18H: u8 = 0xDF;
+
+// 0xE*
+pub const LDH_a8_A: u8 = 0xE0;
+pub const POP_HL: u8 = 0xE1;
+pub const LD_aC_A: u8 = 0xE2;
+pub const PUSH_HL: u8 = 0xE5;
+pub const AND_d8: u8 = 0xE6;
+pub const RST_20H: u8 = 0xE7;
+pub const ADD_SP_s8: u8 = 0xE8;
+pub const JP_HL: u8 = 0xE9;
+pub const LD_a16_A: u8 = 0xEA;
+pub const XOR_d8: u8 = 0xEE;
+pub const RST_28H: u8 = 0xEF;
+
+// 0xF*
+pub const LDH_A_a8: u8 = 0xF0;
+pub const POP_AF: u8 = 0xF1;
+pub const LD_A_aC: u8 = 0xF2;
+pub const DI: u8 = 0xF3;
+pub const PUSH_AF: u8 = 0xF5;
+pub const OR_d8: u8 = 0xF6;
+pub const RST_30H: u8 = 0xF7;
 
 /*
     0xF8 LD HL, SP+s8
@@ -198,3 +297,11 @@ pub const PREFIX: u8 = 0xCB;
     Add signed integer 8 to stack pointer, store result in hl
 */
 pub const LD_HL_SP_ADD_s8: u8 = 0xF8;
+
+pub const LD_SP_HL: u8 = 0xF9;
+pub const LD_A_a16: u8 = 0xFA;
+pub const EI: u8 = 0xFB;
+pub const CP_d8: u8 = 0xFE;
+pub const RST_38H: u8 = 0xFF;
+
+// 0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC and 0xFD are unused
@@ -0,0 +1,116 @@
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+use wasm_bindgen::prelude::*;
+
+// P1/JOYP
+//
+// Writing P14 (bit 4) or P15 (bit 5) low selects the direction keys or the buttons onto the
+// low nibble, where a pressed key reads as 0. Both can be selected at once.
+
+pub const P1: u16 = 0xFF00;
+
+const SELECT_DIRECTIONS: u8 = 0x10;
+const SELECT_BUTTONS: u8 = 0x20;
+
+#[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), wasm_bindgen)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+#[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), wasm_bindgen)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ButtonState {
+    pub right: bool,
+    pub left: bool,
+    pub up: bool,
+    pub down: bool,
+    pub a: bool,
+    pub b: bool,
+    pub select: bool,
+    pub start: bool,
+}
+
+#[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), wasm_bindgen)]
+impl ButtonState {
+    // Nothing pressed
+    #[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), wasm_bindgen(constructor))]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, button: Button, pressed: bool) {
+        match button {
+            Button::Right => self.right = pressed,
+            Button::Left => self.left = pressed,
+            Button::Up => self.up = pressed,
+            Button::Down => self.down = pressed,
+            Button::A => self.a = pressed,
+            Button::B => self.b = pressed,
+            Button::Select => self.select = pressed,
+            Button::Start => self.start = pressed,
+        }
+    }
+}
+
+impl ButtonState {
+    // Directions in the low nibble and buttons in the high, in P1 bit order, set when pressed
+    fn bits(&self) -> u8 {
+        [self.right, self.left, self.up, self.down, self.a, self.b, self.select, self.start]
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (i, pressed)| bits | ((*pressed as u8) << i))
+    }
+}
+
+pub struct Joypad {
+    select: u8,
+    buttons: ButtonState,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Self { select: 0x00, buttons: ButtonState::default() }
+    }
+
+    // P10-P13 lines pulled low, set for each
+    pub fn lines(&self) -> u8 {
+        let bits = self.buttons.bits();
+        let mut lines = 0;
+        if self.select & SELECT_DIRECTIONS == 0 {
+            lines |= bits & 0x0F;
+        }
+        if self.select & SELECT_BUTTONS == 0 {
+            lines |= bits >> 4;
+        }
+        lines
+    }
+
+    pub fn read(&self) -> u8 {
+        0xC0 | self.select | (!self.lines() & 0x0F)
+    }
+
+    // Returns true if a line went low, which requests the joypad interrupt
+    pub fn write(&mut self, value: u8) -> bool {
+        let before = self.lines();
+        self.select = value & (SELECT_DIRECTIONS | SELECT_BUTTONS);
+        self.lines() & !before != 0
+    }
+
+    pub fn buttons(&self) -> ButtonState {
+        self.buttons
+    }
+
+    // Returns true if a line went low, which requests the joypad interrupt
+    pub fn set_buttons(&mut self, buttons: ButtonState) -> bool {
+        let before = self.lines();
+        self.buttons = buttons;
+        self.lines() & !before != 0
+    }
+}
@@ -1,13 +1,44 @@
 mod word;
 mod registers;
 mod memory;
+mod timer;
+mod joypad;
+mod serial;
+mod ppu;
+mod dma;
+mod link;
+mod dmg07;
+mod printer;
+mod mobile;
+mod png;
+mod palette;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+mod tcp_link;
+mod cartridge;
+mod boot_rom;
+mod emulator;
+mod model;
+mod init;
 mod cpu;
 mod instructions;
 
 #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
 use wasm_bindgen::prelude::*;
 
-use cpu::LR35902;
+pub use cartridge::{Cartridge, MapperKind};
+pub use emulator::{Emulator, MAX_SCREENSHOT_SCALE};
+pub use model::Model;
+pub use init::{InitPattern, InitPolicy};
+pub use joypad::{Button, ButtonState};
+pub use serial::{Capture, Disconnected, SerialDevice};
+pub use link::{link_cable, Link, LinkPort};
+pub use dmg07::{Dmg07, Dmg07Port};
+pub use printer::{PrintedPage, Printer};
+pub use mobile::{MobileAdapter, MobileBackend, MockServer};
+pub use ppu::{Layer, LockedAccess, LockedAccessPolicy, Mode, Renderer};
+pub use palette::{Palette, PalettePreset, Palettes};
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use tcp_link::TcpLink;
 
 #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
 #[wasm_bindgen]
@@ -23,10 +54,4 @@ extern "C" {
 #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
 pub fn log(s: &str) {
     println!("{s}");
-}
-
-#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
-#[wasm_bindgen]
-pub fn run(rom: Vec<u8>) {
-    LR35902::open(rom).run();
 }
\ No newline at end of file
@@ -0,0 +1,156 @@
+// A link cable between two emulators in the same process
+//
+// Each end is a SerialDevice. Whichever Game Boy uses the internal clock is master: when it
+// clocks a byte, the other end receives it and returns what it had waiting, provided it was
+// armed on the external clock. If it wasn't the master reads 0xFF and the byte is lost, as
+// the slave's game wasn't listening.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::emulator::Emulator;
+use crate::serial::SerialDevice;
+
+#[derive(Default)]
+struct End {
+    // SB of a Game Boy armed on the external clock, refreshed every M-cycle it stays armed
+    waiting: Option<u8>,
+    // Byte clocked in by the partner, for the next poll to pick up
+    delivered: Option<u8>,
+}
+
+#[derive(Default)]
+struct Cable {
+    ends: [End; 2],
+}
+
+pub struct LinkPort {
+    cable: Rc<RefCell<Cable>>,
+    side: usize,
+}
+
+// Both ends of a new cable
+pub fn link_cable() -> (LinkPort, LinkPort) {
+    let cable = Rc::new(RefCell::new(Cable::default()));
+    (LinkPort { cable: Rc::clone(&cable), side: 0 }, LinkPort { cable, side: 1 })
+}
+
+impl SerialDevice for LinkPort {
+    fn exchange(&mut self, outgoing: u8) -> u8 {
+        let mut cable = self.cable.borrow_mut();
+        let partner = &mut cable.ends[1 - self.side];
+        match partner.waiting.take() {
+            Some(incoming) => {
+                partner.delivered = Some(outgoing);
+                incoming
+            }
+            None => 0xFF,
+        }
+    }
+
+    fn external_clock(&mut self, outgoing: u8) -> Option<u8> {
+        let mut cable = self.cable.borrow_mut();
+        let end = &mut cable.ends[self.side];
+        let delivered = end.delivered.take();
+        if delivered.is_none() {
+            end.waiting = Some(outgoing);
+        }
+        delivered
+    }
+
+    fn tick(&mut self, _m_cycles: u64) {
+        // Stops being available to the master unless external_clock says otherwise this cycle
+        self.cable.borrow_mut().ends[self.side].waiting = None;
+    }
+}
+
+// Two emulators joined by a link cable, kept within a bounded number of M-cycles of each other
+pub struct Link {
+    pub emulators: [Emulator; 2],
+    max_skew: u64,
+}
+
+impl Link {
+    // Connects the pair, replacing anything in their link ports. They start in lock-step, one
+    // instruction at a time.
+    pub fn new(mut first: Emulator, mut second: Emulator) -> Self {
+        let (a, b) = link_cable();
+        first.connect_serial(Box::new(a));
+        second.connect_serial(Box::new(b));
+        Self { emulators: [first, second], max_skew: 0 }
+    }
+
+    // Let one run up to this many M-cycles ahead before switching to the other. Faster, but a
+    // byte offered by one side may be seen late by the other.
+    pub fn with_max_skew(mut self, m_cycles: u64) -> Self {
+        self.max_skew = m_cycles;
+        self
+    }
+
+    // Run whichever emulator is behind until it is ahead by more than the allowed skew
+    pub fn step(&mut self) {
+        let [first, second] = &mut self.emulators;
+        let (behind, ahead) = if first.cycles() <= second.cycles() { (first, second) } else { (second, first) };
+        let target = ahead.cycles() + self.max_skew;
+        while behind.cycles() <= target {
+            behind.step();
+        }
+    }
+
+    // Run until both have done at least this many more M-cycles
+    pub fn run_for(&mut self, m_cycles: u64) {
+        let end = self.emulators.iter().map(|e| e.cycles()).max().unwrap_or(0) + m_cycles;
+        while self.emulators.iter().any(|e| e.cycles() < end) {
+            self.step();
+        }
+    }
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::model::Model;
+
+    // Offer a byte over serial, wait for the transfer, then HALT with what came back in A
+    pub(crate) fn exchange_rom(byte: u8, sc: u8) -> Vec<u8> {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0100..0x0111].copy_from_slice(&[
+            0x3E, byte, // LD A,byte
+            0xE0, 0x01, // LDH (SB),A
+            0x3E, sc,   // LD A,sc
+            0xE0, 0x02, // LDH (SC),A
+            0xF0, 0x02, // LDH A,(SC)
+            0x87,       // ADD A,A
+            0x38, 0xFB, // JR C,-5
+            0xF0, 0x01, // LDH A,(SB)
+            0x76,       // HALT
+            0x00,
+        ]);
+        rom
+    }
+
+    #[test]
+    fn master_and_slave_swap_bytes() {
+        for max_skew in [0, 100] {
+            let master = Emulator::new(exchange_rom(0x12, 0x81), Model::Dmg).unwrap();
+            let slave = Emulator::new(exchange_rom(0x34, 0x80), Model::Dmg).unwrap();
+            let mut link = Link::new(master, slave).with_max_skew(max_skew);
+            link.run_for(4000);
+
+            let [master, slave] = &link.emulators;
+            assert!(master.cpu().halted && slave.cpu().halted);
+            assert_eq!(master.cpu().registers.af.0, 0x34);
+            assert_eq!(slave.cpu().registers.af.0, 0x12);
+        }
+    }
+
+    #[test]
+    fn slave_not_listening() {
+        let master = Emulator::new(exchange_rom(0x12, 0x81), Model::Dmg).unwrap();
+        let idle = Emulator::new(vec![0u8; 0x8000], Model::Dmg).unwrap();
+        let mut link = Link::new(master, idle);
+        link.run_for(4000);
+        assert_eq!(link.emulators[0].cpu().registers.af.0, 0xFF);
+    }
+}
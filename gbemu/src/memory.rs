@@ -1,47 +1,386 @@
-use std::ops::{Index, IndexMut};
+use crate::cartridge::Cartridge;
+use crate::dma::{self, Dma};
+use crate::log as console_log;
+use crate::init::{InitPolicy, Rng};
+use crate::joypad::{self, ButtonState, Joypad};
+use crate::model::Model;
+use crate::ppu::{self, LockedAccess, LockedAccessPolicy, Ppu, Renderer};
+use crate::serial::{self, Serial, SerialDevice};
+use crate::timer::{self, Timer};
+
+pub const IF: u16 = 0xFF0F;
+pub const IE: u16 = 0xFFFF;
+pub const BOOT: u16 = 0xFF50;
+pub const KEY1: u16 = 0xFF4D;
+
+// Interrupt sources, in IF/IE bit order, which is also priority order
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Interrupt {
+    VBlank,
+    Stat,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+impl Interrupt {
+    pub const ALL: [Interrupt; 5] = [Self::VBlank, Self::Stat, Self::Timer, Self::Serial, Self::Joypad];
+
+    // Address the CPU calls to service it
+    pub fn vector(self) -> u16 {
+        0x0040 + 8 * self as u16
+    }
+}
 
 pub struct Memory {
+    model: Model,
+    init_policy: InitPolicy,
+    cartridge: Cartridge,
+    timer: Timer,
+    joypad: Joypad,
+    serial: Serial,
+    ppu: Ppu,
+    dma: Dma,
+    locked_access_policy: LockedAccessPolicy,
+    // The first access blocked since last taken, when breaking on them
+    locked_access: Option<LockedAccess>,
+    // Mapped over the cartridge at 0x0000-0x00FF (and 0x0200-0x08FF for CGB) until 0xFF50 is written
+    boot_rom: Option<Vec<u8>>,
+    // Set IO as the model's boot ROM leaves it once the mapped one hands over, for boot ROMs
+    // that can't themselves, such as the free one with DIV and STAT
+    post_boot_io_at_handover: bool,
+    // KEY1: the next STOP switches speed, and whether the CPU runs at double speed
+    speed_switch_armed: bool,
+    double_speed: bool,
+    // In double speed, whether an M-cycle is left over that the cartridge's clock hasn't seen
+    half_cycle: bool,
     memory: [u8; 65536]
 }
 
 impl Memory {
-    pub fn new() -> Self {
-        // TODO initial memory
-        Memory { memory: [0u8; 65536] }
+    // Zeroed, see initialise for anything else
+    pub fn new(cartridge: Cartridge, model: Model) -> Self {
+        Self {
+            model,
+            init_policy: InitPolicy::default(),
+            cartridge,
+            timer: Timer::new(),
+            joypad: Joypad::new(),
+            serial: Serial::new(model.is_cgb()),
+            ppu: Ppu::new(model.has_stat_write_bug()),
+            dma: Dma::new(),
+            locked_access_policy: LockedAccessPolicy::default(),
+            locked_access: None,
+            boot_rom: None,
+            post_boot_io_at_handover: false,
+            speed_switch_armed: false,
+            double_speed: false,
+            half_cycle: false,
+            memory: [0u8; 65536],
+        }
+    }
+
+    // Fill RAM as it might be at power on
+    pub fn initialise(&mut self, policy: &InitPolicy, rng: &mut Rng) {
+        self.init_policy = *policy;
+        // VRAM, WRAM, OAM and HRAM
+        for range in [0x8000..=0x9FFF, 0xC000..=0xDFFF, 0xFE00..=0xFE9F, 0xFF80..=0xFFFE] {
+            for addr in range {
+                let value = policy.byte(rng);
+                match addr {
+                    0x8000..=0x9FFF => self.ppu.write_vram(addr, value),
+                    0xFE00..=0xFE9F => self.ppu.write_oam(addr, value),
+                    _ => self.memory[addr as usize] = value,
+                }
+            }
+        }
+    }
+
+    // Skip the boot ROM, leaving IO and the cartridge as it would have
+    pub fn post_boot(&mut self) {
+        self.post_boot_io();
+        self.cartridge.post_boot();
+    }
+
+    fn post_boot_io(&mut self) {
+        for (addr, value) in self.model.post_boot_io() {
+            match addr {
+                timer::DIV..=timer::TAC => self.timer.restore(addr, value),
+                serial::SB | serial::SC => self.serial.restore(addr, value),
+                ppu::LCDC..=ppu::LYC | ppu::BGP..=ppu::WX => self.ppu.restore(addr, value),
+                dma::DMA => self.dma.restore(value),
+                KEY1 => self.write_key1(value),
+                joypad::P1 => {
+                    self.joypad.write(value);
+                }
+                _ => self.memory[addr as usize] = value,
+            }
+        }
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    pub fn init_policy(&self) -> InitPolicy {
+        self.init_policy
+    }
+
+    // Registers that only exist on the CGB: speed switch, VRAM bank, HDMA, IR, palettes, WRAM bank
+    fn is_cgb_register(addr: u16) -> bool {
+        matches!(addr, 0xFF4D | 0xFF4F | 0xFF51..=0xFF56 | 0xFF68..=0xFF6C | 0xFF70)
+    }
+
+    pub fn map_boot_rom(&mut self, boot_rom: Vec<u8>) {
+        self.boot_rom = Some(boot_rom);
+    }
+
+    pub fn set_post_boot_io_at_handover(&mut self) {
+        self.post_boot_io_at_handover = true;
+    }
+
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_rom.is_some()
     }
 
     pub fn set8(&mut self, addr: u16, value: u8) {
-        self.memory[addr as usize] = value;
+        self.cartridge.bus_access(addr);
+        if self.dma.active() && addr < 0xFF00 {
+            return;
+        }
+
+        match addr {
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => self.cartridge.write8(addr, value),
+            0x8000..=0x9FFF if self.ppu.vram_locked() => self.locked(addr, Some(value)),
+            0x8000..=0x9FFF => self.ppu.write_vram(addr, value),
+            0xFE00..=0xFE9F if self.ppu.oam_locked() => self.locked(addr, Some(value)),
+            0xFE00..=0xFE9F => self.ppu.write_oam(addr, value),
+            BOOT => {
+                if value != 0 && self.boot_rom.take().is_some() && self.post_boot_io_at_handover {
+                    self.post_boot_io();
+                }
+            }
+            joypad::P1 => {
+                if self.joypad.write(value) {
+                    self.request_interrupt(Interrupt::Joypad);
+                }
+            }
+            serial::SB | serial::SC => self.serial.write(addr, value),
+            timer::DIV..=timer::TAC => self.timer.write(addr, value),
+            ppu::LCDC..=ppu::LYC | ppu::BGP..=ppu::WX => self.ppu.write(addr, value),
+            dma::DMA => self.dma.write(value),
+            KEY1 if self.model.supports_double_speed() => self.write_key1(value),
+            _ if !self.model.is_cgb() && Self::is_cgb_register(addr) => (),
+            _ => self.memory[addr as usize] = value,
+        }
     }
 
-    pub fn get8(&self, addr: u16) -> u8 {
-        self.memory[addr as usize]
+    pub fn get8(&mut self, addr: u16) -> u8 {
+        self.cartridge.bus_access(addr);
+        if self.dma.active() && addr < 0xFF00 {
+            return self.dma.conflict(addr);
+        }
+        if let Some(value) = self.read_boot_rom(addr) {
+            return value;
+        }
+
+        match addr {
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => self.cartridge.read8(addr),
+            0x8000..=0x9FFF if self.ppu.vram_locked() => {
+                self.locked(addr, None);
+                0xFF
+            }
+            0x8000..=0x9FFF => self.ppu.read_vram(addr),
+            0xFE00..=0xFE9F if self.ppu.oam_locked() => {
+                self.locked(addr, None);
+                0xFF
+            }
+            0xFE00..=0xFE9F => self.ppu.read_oam(addr),
+            BOOT => 0xFF,
+            joypad::P1 => self.joypad.read(),
+            serial::SB | serial::SC => self.serial.read(addr),
+            timer::DIV..=timer::TAC => self.timer.read(addr),
+            ppu::LCDC..=ppu::LYC | ppu::BGP..=ppu::WX => self.ppu.read(addr),
+            dma::DMA => self.dma.read(),
+            KEY1 if self.model.supports_double_speed() => {
+                0x7E | (self.double_speed as u8) << 7 | self.speed_switch_armed as u8
+            }
+            _ if !self.model.is_cgb() && Self::is_cgb_register(addr) => 0xFF,
+            _ => self.memory[addr as usize],
+        }
+    }
+
+    // What DMA reads from its source, echo RAM included
+    fn dma_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => self.cartridge.read8(addr),
+            0x8000..=0x9FFF if self.ppu.vram_locked() => 0xFF,
+            0x8000..=0x9FFF => self.ppu.read_vram(addr),
+            0xE000..=0xFFFF => self.memory[addr as usize - 0x2000],
+            _ => self.memory[addr as usize],
+        }
+    }
+
+    // The CPU touched VRAM or OAM while the PPU had it
+    fn locked(&mut self, addr: u16, value: Option<u8>) {
+        let access = LockedAccess { addr, value, mode: self.ppu.mode() };
+        match self.locked_access_policy {
+            LockedAccessPolicy::Ignore => (),
+            LockedAccessPolicy::Log => match value {
+                Some(value) => console_log(format!("Write of {value:#04x} to {addr:#06x} blocked in {:?}", access.mode).as_str()),
+                None => console_log(format!("Read of {addr:#06x} blocked in {:?}", access.mode).as_str()),
+            },
+            LockedAccessPolicy::Break => {
+                self.locked_access.get_or_insert(access);
+            }
+        }
+    }
+
+    pub fn set_locked_access_policy(&mut self, policy: LockedAccessPolicy) {
+        self.locked_access_policy = policy;
     }
 
+    pub fn locked_access(&self) -> Option<LockedAccess> {
+        self.locked_access
+    }
+
+    pub fn take_locked_access(&mut self) -> Option<LockedAccess> {
+        self.locked_access.take()
+    }
+
+    fn read_boot_rom(&self, addr: u16) -> Option<u8> {
+        let boot_rom = self.boot_rom.as_ref()?;
+        match addr {
+            0x0000..=0x00FF | 0x0200..=0x08FF => boot_rom.get(addr as usize).copied(),
+            _ => None,
+        }
+    }
+
+    // Little endian, as the CPU sees it
     pub fn set16(&mut self, addr: u16, value: u16) {
-        self.memory[addr as usize] = (value >> 8) as u8;
-        self.memory[addr as usize + 1] = (value & 0x0F) as u8;
+        self.set8(addr, (value & 0xFF) as u8);
+        self.set8(addr.wrapping_add(1), (value >> 8) as u8);
     }
 
-    pub fn get16(&self, addr: u16) -> u16 {
-        ((self.memory[addr as usize] as u16) << 8) | (self.memory[addr as usize + 1] as u16)
+    // Interrupts both requested and enabled, one bit each for VBlank, STAT, Timer, Serial, Joypad
+    pub fn pending_interrupts(&self) -> u8 {
+        self.memory[IE as usize] & self.memory[IF as usize] & 0x1F
     }
 
-    pub fn len(&self) -> usize {
-        self.memory.len()
+    pub fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        self.memory[IF as usize] &= !(1 << interrupt as u8);
     }
-}
 
-impl Index<u16> for Memory {
-    type Output = u8;
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        self.memory[IF as usize] |= 1 << interrupt as u8;
+    }
 
-    fn index(&self, index: u16) -> &Self::Output {
-        self.memory.index(index as usize)
+    pub fn buttons(&self) -> ButtonState {
+        self.joypad.buttons()
     }
-}
 
-impl IndexMut<u16> for Memory {
-    fn index_mut(&mut self, index: u16) -> &mut Self::Output {
-        self.memory.index_mut(index as usize)
+    pub fn set_buttons(&mut self, buttons: ButtonState) {
+        if self.joypad.set_buttons(buttons) {
+            self.request_interrupt(Interrupt::Joypad);
+        }
     }
-}
\ No newline at end of file
+
+    // Plug something into the link port
+    pub fn connect_serial(&mut self, device: Box<dyn SerialDevice>) {
+        self.serial.connect(device);
+    }
+
+    // Whether any selected joypad line is low, which is what wakes the CPU from STOP
+    pub fn joypad_held(&self) -> bool {
+        self.joypad.lines() != 0
+    }
+
+    // Only the prepare bit is writable, the current speed changes on STOP
+    fn write_key1(&mut self, value: u8) {
+        self.speed_switch_armed = value & 0x01 != 0;
+    }
+
+    // STOP resets DIV, which can tick TIMA like any other DIV write. With a speed switch
+    // prepared it switches speed instead of stopping, returning true. The pause while the clock
+    // settles isn't modelled.
+    pub fn stop(&mut self) -> bool {
+        self.timer.write(timer::DIV, 0);
+        if !self.speed_switch_armed {
+            return false;
+        }
+        self.speed_switch_armed = false;
+        self.double_speed = !self.double_speed;
+        self.half_cycle = false;
+        true
+    }
+
+    // With the CPU stopped only the link port runs, as whatever is plugged in still can
+    pub fn tick_stopped(&mut self, m_cycles: u64) {
+        for _ in 0..m_cycles {
+            if self.serial.step_stopped() {
+                self.request_interrupt(Interrupt::Serial);
+            }
+        }
+    }
+
+    // In double speed the timer, serial and DMA are clocked with the CPU, so take half as long,
+    // while the PPU and the cartridge's clock keep to real time
+    pub fn tick(&mut self, m_cycles: u64) {
+        let dots = if self.double_speed { ppu::DOTS_PER_M_CYCLE / 2 } else { ppu::DOTS_PER_M_CYCLE };
+        for _ in 0..m_cycles {
+            if self.timer.step() {
+                self.request_interrupt(Interrupt::Timer);
+            }
+            if self.serial.step() {
+                self.request_interrupt(Interrupt::Serial);
+            }
+            if let Some((source, oam)) = self.dma.step() {
+                let value = self.dma_read(source);
+                self.dma.moved(value);
+                if !self.ppu.oam_locked() {
+                    self.ppu.write_oam(oam, value);
+                }
+            }
+            let requests = self.ppu.step(dots);
+            if requests.vblank {
+                self.request_interrupt(Interrupt::VBlank);
+            }
+            if requests.stat {
+                self.request_interrupt(Interrupt::Stat);
+            }
+        }
+        let real_cycles = if self.double_speed {
+            let cycles = m_cycles + self.half_cycle as u64;
+            self.half_cycle = cycles % 2 == 1;
+            cycles / 2
+        }
+        else {
+            m_cycles
+        };
+        self.cartridge.tick(real_cycles);
+    }
+
+    pub fn framebuffer(&self) -> &[u8] {
+        self.ppu.framebuffer()
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.ppu.frames()
+    }
+
+    pub fn unsafe_lcd_off(&self) -> bool {
+        self.ppu.unsafe_lcd_off()
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.ppu.set_renderer(renderer);
+    }
+
+    pub fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    pub fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+}
@@ -0,0 +1,668 @@
+// Mobile Adapter GB
+//
+// The Game Boy is master. It sends packets of:
+//   0x99 0x66, command, 0x00, data length (BE), data, checksum (BE)
+// then its device ID (0x80), while the adapter answers with its own, then 0x00 while the adapter
+// acknowledges with the command ^ 0x80, or an error. The checksum is the sum of every byte from
+// the command to the end of the data. The adapter answers 0xD2 while idle.
+//
+// The Game Boy then sends 0x4B while it waits for the reply, a packet the other way with the
+// command | 0x80, and the Game Boy acknowledges it the same way. Failures are reported with a
+// reply to command 0x6E holding the command and an error code.
+//
+// The network behind the adapter is a MobileBackend. The default, MockServer, stands in for the
+// services games expect from the ISP, so they can be played offline.
+//
+// 32 bit transfers, which the adapter can be switched to, aren't emulated: it stays in 8 bit mode.
+
+use std::collections::HashMap;
+
+use crate::serial::SerialDevice;
+
+const MAGIC: [u8; 2] = [0x99, 0x66];
+const GAME_BOY_ID: u8 = 0x80;
+// The blue adapter, for PDC phones
+const ADAPTER_ID: u8 = 0x88;
+const IDLE: u8 = 0xD2;
+
+const BEGIN_SESSION: u8 = 0x10;
+const END_SESSION: u8 = 0x11;
+const DIAL: u8 = 0x12;
+const HANG_UP: u8 = 0x13;
+const WAIT_FOR_CALL: u8 = 0x14;
+const TRANSFER_DATA: u8 = 0x15;
+const RESET: u8 = 0x16;
+const TELEPHONE_STATUS: u8 = 0x17;
+const SIO32_MODE: u8 = 0x18;
+const READ_CONFIG: u8 = 0x19;
+const WRITE_CONFIG: u8 = 0x1A;
+const TRANSFER_END: u8 = 0x1F;
+const ISP_LOGIN: u8 = 0x21;
+const ISP_LOGOUT: u8 = 0x22;
+const OPEN_TCP: u8 = 0x23;
+const CLOSE_TCP: u8 = 0x24;
+const DNS_QUERY: u8 = 0x28;
+const ERROR: u8 = 0x6E;
+
+// Acknowledgements in place of command ^ 0x80
+const UNKNOWN_COMMAND: u8 = 0xF0;
+const CHECKSUM_ERROR: u8 = 0xF1;
+
+const SESSION_KEY: &[u8] = b"NINTENDO";
+const CONFIG_SIZE: usize = 0xC0;
+const MAX_DATA: usize = 0xFE;
+const CONNECTIONS: usize = 2;
+// Connection ID for data over the telephone line itself
+const TELEPHONE: u8 = 0xFF;
+
+const ADAPTER_IP: [u8; 4] = [127, 0, 0, 2];
+const DNS_IP: [u8; 4] = [127, 0, 0, 1];
+
+// The network beyond the telephone line
+pub trait MobileBackend {
+    fn resolve(&mut self, name: &str) -> Option<[u8; 4]>;
+
+    // Opens a TCP connection, returning a socket for the other calls
+    fn connect(&mut self, ip: [u8; 4], port: u16) -> Option<usize>;
+
+    fn send(&mut self, socket: usize, data: &[u8]);
+
+    // Up to max bytes received, or None once the other end has closed and everything was read
+    fn receive(&mut self, socket: usize, max: usize) -> Option<Vec<u8>>;
+
+    fn close(&mut self, socket: usize);
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum State {
+    Magic(usize),
+    Header,
+    Data,
+    Checksum,
+    DeviceId,
+    Acknowledge,
+    Reply,
+}
+
+pub struct MobileAdapter {
+    backend: Box<dyn MobileBackend>,
+    config: Vec<u8>,
+    state: State,
+    packet: Vec<u8>,
+    length: usize,
+    acknowledgement: u8,
+    reply: Vec<u8>,
+    sent: usize,
+    session: bool,
+    line: bool,
+    logged_in: bool,
+    connections: [Option<usize>; CONNECTIONS],
+}
+
+impl Default for MobileAdapter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MobileAdapter {
+    pub fn new() -> Self {
+        Self::with_backend(Box::new(MockServer::new()))
+    }
+
+    pub fn with_backend(backend: Box<dyn MobileBackend>) -> Self {
+        Self {
+            backend,
+            config: vec![0; CONFIG_SIZE],
+            state: State::Magic(0),
+            packet: Vec::new(),
+            length: 0,
+            acknowledgement: 0,
+            reply: Vec::new(),
+            sent: 0,
+            session: false,
+            line: false,
+            logged_in: false,
+            connections: [None; CONNECTIONS],
+        }
+    }
+
+    // The configuration EEPROM, as saved from an earlier session
+    pub fn with_config(mut self, config: &[u8]) -> Self {
+        let len = config.len().min(CONFIG_SIZE);
+        self.config[..len].copy_from_slice(&config[..len]);
+        self
+    }
+
+    pub fn config(&self) -> &[u8] {
+        &self.config
+    }
+
+    fn checksum(bytes: &[u8]) -> u16 {
+        bytes.iter().fold(0u16, |sum, byte| sum.wrapping_add(*byte as u16))
+    }
+
+    fn receive(&mut self, byte: u8) {
+        self.state = match self.state {
+            State::Magic(0) if byte == MAGIC[0] => State::Magic(1),
+            State::Magic(1) if byte == MAGIC[1] => {
+                self.packet.clear();
+                State::Header
+            }
+            State::Magic(_) => if byte == MAGIC[0] { State::Magic(1) } else { State::Magic(0) },
+            State::Header => {
+                self.packet.push(byte);
+                if self.packet.len() < 4 {
+                    State::Header
+                }
+                else {
+                    self.length = u16::from_be_bytes([self.packet[2], self.packet[3]]) as usize;
+                    if self.length == 0 { State::Checksum } else { State::Data }
+                }
+            }
+            State::Data => {
+                self.packet.push(byte);
+                if self.packet.len() == 4 + self.length { State::Checksum } else { State::Data }
+            }
+            State::Checksum => {
+                self.packet.push(byte);
+                if self.packet.len() < 6 + self.length {
+                    State::Checksum
+                }
+                else {
+                    let (body, sum) = self.packet.split_at(4 + self.length);
+                    let command = body[0];
+                    self.acknowledgement = if Self::checksum(body) != u16::from_be_bytes([sum[0], sum[1]]) {
+                        CHECKSUM_ERROR
+                    }
+                    else if !self.session && command != BEGIN_SESSION {
+                        UNKNOWN_COMMAND
+                    }
+                    else {
+                        command ^ 0x80
+                    };
+                    State::DeviceId
+                }
+            }
+            State::DeviceId if byte == GAME_BOY_ID => State::Acknowledge,
+            State::DeviceId => State::Magic(0),
+            State::Acknowledge => {
+                if self.acknowledgement & 0xF0 == UNKNOWN_COMMAND {
+                    State::Magic(0)
+                }
+                else {
+                    let command = self.packet[0];
+                    let data = self.packet[4..4 + self.length].to_vec();
+                    let (command, data) = match self.process(command, &data) {
+                        Ok(reply) => reply,
+                        Err(code) => (ERROR, vec![command, code]),
+                    };
+                    self.reply = Self::frame(command | 0x80, &data);
+                    self.sent = 0;
+                    State::Reply
+                }
+            }
+            State::Reply => {
+                self.sent += 1;
+                if self.sent == self.reply.len() { State::Magic(0) } else { State::Reply }
+            }
+        };
+    }
+
+    fn frame(command: u8, data: &[u8]) -> Vec<u8> {
+        let mut body = vec![command, 0x00];
+        body.extend((data.len() as u16).to_be_bytes());
+        body.extend_from_slice(data);
+        let sum = Self::checksum(&body);
+
+        let mut reply = MAGIC.to_vec();
+        reply.extend(body);
+        reply.extend(sum.to_be_bytes());
+        // The adapter's ID, then 0x00 while the Game Boy acknowledges
+        reply.extend([ADAPTER_ID, 0x00]);
+        reply
+    }
+
+    fn close_all(&mut self) {
+        for socket in self.connections.iter_mut().filter_map(Option::take) {
+            self.backend.close(socket);
+        }
+    }
+
+    // The reply's command and data, or an error code
+    fn process(&mut self, command: u8, data: &[u8]) -> Result<(u8, Vec<u8>), u8> {
+        match command {
+            BEGIN_SESSION if data == SESSION_KEY => {
+                self.session = true;
+                Ok((command, data.to_vec()))
+            }
+            BEGIN_SESSION => Err(0x02),
+            END_SESSION | RESET => {
+                self.close_all();
+                self.line = false;
+                self.logged_in = false;
+                self.session = command == RESET;
+                Ok((command, vec![]))
+            }
+            // Every number reaches the ISP
+            DIAL if self.line => Err(0x01),
+            DIAL => {
+                self.line = true;
+                Ok((command, vec![]))
+            }
+            HANG_UP => {
+                self.close_all();
+                self.line = false;
+                self.logged_in = false;
+                Ok((command, vec![]))
+            }
+            // Nobody calls
+            WAIT_FOR_CALL => Err(0x00),
+            TRANSFER_DATA => self.transfer(data),
+            TELEPHONE_STATUS => {
+                let status = if self.line { 0x04 } else { 0x00 };
+                Ok((command, vec![status, 0x4D, 0x00]))
+            }
+            SIO32_MODE => Ok((command, vec![])),
+            READ_CONFIG => {
+                let (offset, len) = (*data.first().ok_or(0x02)? as usize, *data.get(1).ok_or(0x02)? as usize);
+                if offset + len > CONFIG_SIZE || len > MAX_DATA - 1 {
+                    return Err(0x02);
+                }
+                let mut reply = vec![offset as u8];
+                reply.extend_from_slice(&self.config[offset..offset + len]);
+                Ok((command, reply))
+            }
+            WRITE_CONFIG => {
+                let offset = *data.first().ok_or(0x02)? as usize;
+                let bytes = &data[1..];
+                if offset + bytes.len() > CONFIG_SIZE {
+                    return Err(0x02);
+                }
+                self.config[offset..offset + bytes.len()].copy_from_slice(bytes);
+                Ok((command, vec![offset as u8, bytes.len() as u8]))
+            }
+            ISP_LOGIN if !self.line => Err(0x01),
+            ISP_LOGIN => {
+                self.logged_in = true;
+                let mut reply = ADAPTER_IP.to_vec();
+                reply.extend(DNS_IP);
+                reply.extend(DNS_IP);
+                Ok((command, reply))
+            }
+            ISP_LOGOUT => {
+                self.close_all();
+                self.logged_in = false;
+                Ok((command, vec![]))
+            }
+            OPEN_TCP if !self.logged_in || data.len() != 6 => Err(0x01),
+            OPEN_TCP => {
+                let id = self.connections.iter().position(Option::is_none).ok_or(0x00)?;
+                let ip = [data[0], data[1], data[2], data[3]];
+                let port = u16::from_be_bytes([data[4], data[5]]);
+                let socket = self.backend.connect(ip, port).ok_or(0x03)?;
+                self.connections[id] = Some(socket);
+                Ok((command, vec![id as u8]))
+            }
+            CLOSE_TCP => {
+                let id = *data.first().ok_or(0x01)?;
+                let socket = self.connections.get_mut(id as usize).and_then(Option::take).ok_or(0x01)?;
+                self.backend.close(socket);
+                Ok((command, vec![id]))
+            }
+            DNS_QUERY if !self.logged_in => Err(0x01),
+            DNS_QUERY => {
+                let name = String::from_utf8_lossy(data);
+                let ip = self.backend.resolve(&name).ok_or(0x02)?;
+                Ok((command, ip.to_vec()))
+            }
+            _ => Err(0x00),
+        }
+    }
+
+    fn transfer(&mut self, data: &[u8]) -> Result<(u8, Vec<u8>), u8> {
+        let id = *data.first().ok_or(0x01)?;
+        if id == TELEPHONE {
+            // No one on the other end of the line to talk to
+            return if self.line { Ok((TRANSFER_DATA, vec![id])) } else { Err(0x01) };
+        }
+
+        let socket = self.connections.get(id as usize).copied().flatten().ok_or(0x01)?;
+        if data.len() > 1 {
+            self.backend.send(socket, &data[1..]);
+        }
+        match self.backend.receive(socket, MAX_DATA - 1) {
+            Some(received) => {
+                let mut reply = vec![id];
+                reply.extend(received);
+                Ok((TRANSFER_DATA, reply))
+            }
+            None => {
+                self.backend.close(socket);
+                self.connections[id as usize] = None;
+                Ok((TRANSFER_END, vec![id]))
+            }
+        }
+    }
+}
+
+impl SerialDevice for MobileAdapter {
+    fn exchange(&mut self, outgoing: u8) -> u8 {
+        let reply = match self.state {
+            State::DeviceId => ADAPTER_ID,
+            State::Acknowledge => self.acknowledgement,
+            State::Reply => self.reply[self.sent],
+            _ => IDLE,
+        };
+        self.receive(outgoing);
+        reply
+    }
+}
+
+#[derive(Default)]
+struct Mail {
+    messages: Vec<Vec<u8>>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Protocol {
+    Smtp,
+    Pop,
+    Http,
+}
+
+struct Socket {
+    protocol: Protocol,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    open: bool,
+    // SMTP message being received after DATA
+    message: Option<Vec<u8>>,
+}
+
+// Stands in for the ISP: every name resolves to it, and it answers SMTP on port 25, POP3 on 110
+// and HTTP on 80. Mail sent over SMTP lands in the mailbox POP3 reads from, and HTTP serves
+// the pages it's given, whatever the host.
+pub struct MockServer {
+    pages: HashMap<String, Vec<u8>>,
+    mail: Mail,
+    sockets: Vec<Option<Socket>>,
+}
+
+impl Default for MockServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockServer {
+    pub fn new() -> Self {
+        Self { pages: HashMap::new(), mail: Mail::default(), sockets: Vec::new() }
+    }
+
+    // Serve body at path, such as "/index.html"
+    pub fn with_page(mut self, path: &str, body: &[u8]) -> Self {
+        self.pages.insert(path.to_string(), body.to_vec());
+        self
+    }
+
+    // Put a message in the mailbox, headers and all
+    pub fn with_mail(mut self, message: &[u8]) -> Self {
+        self.mail.messages.push(message.to_vec());
+        self
+    }
+
+    fn take_line(input: &mut Vec<u8>) -> Option<String> {
+        let end = input.windows(2).position(|pair| pair == b"\r\n")?;
+        let line: Vec<u8> = input.drain(..end + 2).take(end).collect();
+        Some(String::from_utf8_lossy(&line).into_owned())
+    }
+
+    fn smtp(mail: &mut Mail, socket: &mut Socket) {
+        while let Some(line) = Self::take_line(&mut socket.input) {
+            if let Some(message) = socket.message.as_mut() {
+                if line == "." {
+                    mail.messages.push(socket.message.take().unwrap_or_default());
+                    socket.output.extend(b"250 OK\r\n");
+                }
+                else {
+                    message.extend(line.strip_prefix('.').unwrap_or(&line).as_bytes());
+                    message.extend(b"\r\n");
+                }
+                continue;
+            }
+
+            let reply: &[u8] = match line.get(..4).unwrap_or("").to_ascii_uppercase().as_str() {
+                "HELO" | "EHLO" | "MAIL" | "RCPT" | "RSET" | "NOOP" => b"250 OK\r\n",
+                "DATA" => {
+                    socket.message = Some(Vec::new());
+                    b"354 End data with <CR><LF>.<CR><LF>\r\n"
+                }
+                "QUIT" => {
+                    socket.open = false;
+                    b"221 Bye\r\n"
+                }
+                _ => b"502 Command not implemented\r\n",
+            };
+            socket.output.extend(reply);
+        }
+    }
+
+    fn pop(mail: &mut Mail, socket: &mut Socket) {
+        while let Some(line) = Self::take_line(&mut socket.input) {
+            let mut words = line.split_whitespace();
+            let command = words.next().unwrap_or("").to_ascii_uppercase();
+            let message = words.next()
+                .and_then(|n| n.parse::<usize>().ok())
+                .and_then(|n| n.checked_sub(1))
+                .filter(|n| *n < mail.messages.len());
+
+            let reply = match (command.as_str(), message) {
+                ("USER" | "PASS" | "NOOP" | "RSET", _) => "+OK\r\n".to_string(),
+                ("STAT", _) => {
+                    let size: usize = mail.messages.iter().map(Vec::len).sum();
+                    format!("+OK {} {size}\r\n", mail.messages.len())
+                }
+                ("LIST", _) => {
+                    let mut reply = format!("+OK {} messages\r\n", mail.messages.len());
+                    for (i, message) in mail.messages.iter().enumerate() {
+                        reply += &format!("{} {}\r\n", i + 1, message.len());
+                    }
+                    reply + ".\r\n"
+                }
+                ("RETR", Some(n)) => {
+                    socket.output.extend(format!("+OK {} octets\r\n", mail.messages[n].len()).as_bytes());
+                    let message = &mail.messages[n];
+                    for line in message.strip_suffix(b"\r\n").unwrap_or(message).split(|byte| *byte == b'\n') {
+                        let line = line.strip_suffix(b"\r").unwrap_or(line);
+                        if line.first() == Some(&b'.') {
+                            socket.output.push(b'.');
+                        }
+                        socket.output.extend(line);
+                        socket.output.extend(b"\r\n");
+                    }
+                    ".\r\n".to_string()
+                }
+                // Deleted straight away, there being only the one client
+                ("DELE", Some(n)) => {
+                    mail.messages[n].clear();
+                    "+OK\r\n".to_string()
+                }
+                ("QUIT", _) => {
+                    mail.messages.retain(|message| !message.is_empty());
+                    socket.open = false;
+                    "+OK Bye\r\n".to_string()
+                }
+                _ => "-ERR\r\n".to_string(),
+            };
+            socket.output.extend(reply.as_bytes());
+        }
+    }
+
+    fn http(pages: &HashMap<String, Vec<u8>>, socket: &mut Socket) {
+        let Some(end) = socket.input.windows(4).position(|four| four == b"\r\n\r\n") else {
+            return;
+        };
+        let head = String::from_utf8_lossy(&socket.input[..end]).into_owned();
+        let body_length = head.lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("Content-Length"))
+            .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+            .unwrap_or(0);
+        if socket.input.len() < end + 4 + body_length {
+            return;
+        }
+        socket.input.clear();
+
+        let path = head.split_whitespace().nth(1).unwrap_or("/");
+        let (status, body) = match pages.get(path) {
+            Some(body) => ("200 OK", body.as_slice()),
+            None => ("404 Not Found", &b"Not Found"[..]),
+        };
+        socket.output.extend(format!("HTTP/1.0 {status}\r\nContent-Length: {}\r\n\r\n", body.len()).as_bytes());
+        socket.output.extend(body);
+        socket.open = false;
+    }
+}
+
+impl MobileBackend for MockServer {
+    fn resolve(&mut self, _name: &str) -> Option<[u8; 4]> {
+        Some(DNS_IP)
+    }
+
+    fn connect(&mut self, _ip: [u8; 4], port: u16) -> Option<usize> {
+        let (protocol, greeting): (_, &[u8]) = match port {
+            25 => (Protocol::Smtp, b"220 localhost ESMTP\r\n"),
+            110 => (Protocol::Pop, b"+OK POP3 ready\r\n"),
+            80 => (Protocol::Http, b""),
+            _ => return None,
+        };
+        let socket = Socket { protocol, input: Vec::new(), output: greeting.to_vec(), open: true, message: None };
+        self.sockets.push(Some(socket));
+        Some(self.sockets.len() - 1)
+    }
+
+    fn send(&mut self, socket: usize, data: &[u8]) {
+        let Some(Some(socket)) = self.sockets.get_mut(socket) else {
+            return;
+        };
+        if !socket.open {
+            return;
+        }
+        socket.input.extend_from_slice(data);
+        match socket.protocol {
+            Protocol::Smtp => Self::smtp(&mut self.mail, socket),
+            Protocol::Pop => Self::pop(&mut self.mail, socket),
+            Protocol::Http => Self::http(&self.pages, socket),
+        }
+    }
+
+    fn receive(&mut self, socket: usize, max: usize) -> Option<Vec<u8>> {
+        let socket = self.sockets.get_mut(socket)?.as_mut()?;
+        if socket.output.is_empty() && !socket.open {
+            return None;
+        }
+        let len = socket.output.len().min(max);
+        Some(socket.output.drain(..len).collect())
+    }
+
+    fn close(&mut self, socket: usize) {
+        if let Some(socket) = self.sockets.get_mut(socket) {
+            *socket = None;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Send a packet as the Game Boy would and read back the reply's command and data
+    fn request(adapter: &mut MobileAdapter, command: u8, data: &[u8]) -> (u8, Vec<u8>) {
+        let packet = MobileAdapter::frame(command, data);
+        for byte in &packet[..packet.len() - 2] {
+            assert_eq!(adapter.exchange(*byte), IDLE);
+        }
+        assert_eq!(adapter.exchange(GAME_BOY_ID), ADAPTER_ID);
+        assert_eq!(adapter.exchange(0x00), command ^ 0x80);
+
+        let mut reply: Vec<u8> = (0..6).map(|_| adapter.exchange(0x4B)).collect();
+        assert_eq!(reply[..2], MAGIC);
+        let length = u16::from_be_bytes([reply[4], reply[5]]) as usize;
+        reply.extend((0..length + 2).map(|_| adapter.exchange(0x4B)));
+        assert_eq!(adapter.exchange(GAME_BOY_ID), ADAPTER_ID);
+        assert_eq!(adapter.exchange(reply[2] ^ 0x80), 0x00);
+
+        let sum = MobileAdapter::checksum(&reply[2..6 + length]);
+        assert_eq!(reply[6 + length..], sum.to_be_bytes());
+        (reply[2] & 0x7F, reply[6..6 + length].to_vec())
+    }
+
+    #[test]
+    fn rejects_bad_checksums_and_unknown_commands() {
+        let mut adapter = MobileAdapter::new();
+        let mut packet = MobileAdapter::frame(BEGIN_SESSION, SESSION_KEY);
+        packet[6 + SESSION_KEY.len()] ^= 0x01;
+        for byte in &packet[..packet.len() - 2] {
+            adapter.exchange(*byte);
+        }
+        assert_eq!(adapter.exchange(GAME_BOY_ID), ADAPTER_ID);
+        assert_eq!(adapter.exchange(0x00), CHECKSUM_ERROR);
+
+        // Nothing but BEGIN_SESSION until there's a session
+        let packet = MobileAdapter::frame(DIAL, b"0");
+        for byte in &packet[..packet.len() - 2] {
+            adapter.exchange(*byte);
+        }
+        assert_eq!(adapter.exchange(GAME_BOY_ID), ADAPTER_ID);
+        assert_eq!(adapter.exchange(0x00), UNKNOWN_COMMAND);
+        assert_eq!(adapter.exchange(0x4B), IDLE);
+    }
+
+    #[test]
+    fn session_config_and_http() {
+        let server = MockServer::new().with_page("/index.html", b"Hello");
+        let mut adapter = MobileAdapter::with_backend(Box::new(server));
+
+        assert_eq!(request(&mut adapter, BEGIN_SESSION, SESSION_KEY), (BEGIN_SESSION, SESSION_KEY.to_vec()));
+        assert_eq!(request(&mut adapter, WRITE_CONFIG, &[0x10, 1, 2, 3]), (WRITE_CONFIG, vec![0x10, 3]));
+        assert_eq!(request(&mut adapter, READ_CONFIG, &[0x0F, 3]), (READ_CONFIG, vec![0x0F, 0, 1, 2]));
+        assert_eq!(request(&mut adapter, OPEN_TCP, &[127, 0, 0, 1, 0, 80]), (ERROR, vec![OPEN_TCP, 0x01]));
+
+        request(&mut adapter, DIAL, b"#9677");
+        assert_eq!(request(&mut adapter, TELEPHONE_STATUS, &[]).1[0], 0x04);
+        let (_, login) = request(&mut adapter, ISP_LOGIN, b"\x04user\x04pass");
+        assert_eq!(login[..4], ADAPTER_IP);
+        let (_, ip) = request(&mut adapter, DNS_QUERY, b"example.com");
+        let mut address = ip.clone();
+        address.extend(80u16.to_be_bytes());
+        assert_eq!(request(&mut adapter, OPEN_TCP, &address), (OPEN_TCP, vec![0]));
+
+        let (command, response) = request(&mut adapter, TRANSFER_DATA, b"\x00GET /index.html HTTP/1.0\r\n\r\n");
+        assert_eq!(command, TRANSFER_DATA);
+        assert!(response[1..].starts_with(b"HTTP/1.0 200 OK\r\n"));
+        assert!(response.ends_with(b"\r\n\r\nHello"));
+        assert_eq!(request(&mut adapter, TRANSFER_DATA, &[0]), (TRANSFER_END, vec![0]));
+
+        request(&mut adapter, END_SESSION, &[]);
+        assert_eq!(adapter.config()[0x10..0x13], [1, 2, 3]);
+    }
+
+    #[test]
+    fn mail_sent_over_smtp_arrives_over_pop() {
+        let mut server = MockServer::new();
+        let smtp = server.connect(DNS_IP, 25).unwrap();
+        server.send(smtp, b"HELO gb\r\nMAIL FROM:<a@gb>\r\nRCPT TO:<b@gb>\r\nDATA\r\nHi\r\n..dot\r\n.\r\nQUIT\r\n");
+        let log = server.receive(smtp, 1000).unwrap();
+        assert!(log.ends_with(b"250 OK\r\n221 Bye\r\n"));
+        assert_eq!(server.receive(smtp, 1000), None);
+
+        let pop = server.connect(DNS_IP, 110).unwrap();
+        server.send(pop, b"USER b\r\nPASS x\r\nSTAT\r\nRETR 1\r\nDELE 1\r\nQUIT\r\n");
+        let log = String::from_utf8(server.receive(pop, 1000).unwrap()).unwrap();
+        assert!(log.contains("+OK 1 10\r\n"));
+        assert!(log.contains("+OK 10 octets\r\nHi\r\n..dot\r\n.\r\n"));
+        assert!(server.mail.messages.is_empty());
+    }
+}
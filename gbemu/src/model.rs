@@ -0,0 +1,98 @@
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+use wasm_bindgen::prelude::*;
+
+// The console being emulated. Games tell them apart by the registers the boot ROM leaves,
+// mostly A, so picking one is the way to test each of a game's code paths.
+#[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), wasm_bindgen)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Model {
+    // The earliest DMG, with a different boot ROM
+    Dmg0,
+    #[default]
+    Dmg,
+    // Game Boy Pocket and Light
+    Mgb,
+    Sgb,
+    Sgb2,
+    Cgb,
+    // A Game Boy Advance running a Game Boy Color game
+    Agb,
+}
+
+// Registers as the boot ROM leaves them. F is None where it depends on the header checksum.
+pub struct PostBoot {
+    pub a: u8,
+    pub f: Option<u8>,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+}
+
+impl Model {
+    pub fn is_cgb(self) -> bool {
+        matches!(self, Self::Cgb | Self::Agb)
+    }
+
+    pub fn is_sgb(self) -> bool {
+        matches!(self, Self::Sgb | Self::Sgb2)
+    }
+
+    pub fn supports_double_speed(self) -> bool {
+        self.is_cgb()
+    }
+
+    // Writing STAT on monochrome models briefly sets every STAT interrupt source
+    pub fn has_stat_write_bug(self) -> bool {
+        !self.is_cgb()
+    }
+
+    pub fn post_boot(self) -> PostBoot {
+        match self {
+            Self::Dmg0 => PostBoot { a: 0x01, f: Some(0x00), bc: 0xFF13, de: 0x00C1, hl: 0x8403 },
+            Self::Dmg => PostBoot { a: 0x01, f: None, bc: 0x0013, de: 0x00D8, hl: 0x014D },
+            Self::Mgb => PostBoot { a: 0xFF, f: None, bc: 0x0013, de: 0x00D8, hl: 0x014D },
+            Self::Sgb => PostBoot { a: 0x01, f: Some(0x00), bc: 0x0014, de: 0x0000, hl: 0xC060 },
+            Self::Sgb2 => PostBoot { a: 0xFF, f: Some(0x00), bc: 0x0014, de: 0x0000, hl: 0xC060 },
+            Self::Cgb => PostBoot { a: 0x11, f: Some(0x80), bc: 0x0000, de: 0xFF56, hl: 0x000D },
+            Self::Agb => PostBoot { a: 0x11, f: Some(0x00), bc: 0x0100, de: 0xFF56, hl: 0x000D },
+        }
+    }
+
+    // IO registers as the boot ROM leaves them, including the bits which always read back set
+    pub fn post_boot_io(self) -> Vec<(u16, u8)> {
+        let mut io = vec![
+            (0xFF00, 0xCF), (0xFF01, 0x00),
+            (0xFF05, 0x00), (0xFF06, 0x00), (0xFF07, 0xF8), (0xFF0F, 0xE1),
+            (0xFF10, 0x80), (0xFF11, 0xBF), (0xFF12, 0xF3), (0xFF13, 0xFF), (0xFF14, 0xBF),
+            (0xFF16, 0x3F), (0xFF17, 0x00), (0xFF18, 0xFF), (0xFF19, 0xBF),
+            (0xFF1A, 0x7F), (0xFF1B, 0xFF), (0xFF1C, 0x9F), (0xFF1D, 0xFF), (0xFF1E, 0xBF),
+            (0xFF20, 0xFF), (0xFF21, 0x00), (0xFF22, 0x00), (0xFF23, 0xBF),
+            (0xFF24, 0x77), (0xFF25, 0xF3),
+            (0xFF40, 0x91), (0xFF41, 0x85), (0xFF42, 0x00), (0xFF43, 0x00),
+            (0xFF44, 0x00), (0xFF45, 0x00), (0xFF47, 0xFC), (0xFF4A, 0x00), (0xFF4B, 0x00),
+            (0xFFFF, 0x00),
+        ];
+
+        // DIV depends on how long the boot ROM ran. It is only known for the models below,
+        // the others start from zero.
+        let div = match self {
+            Self::Dmg0 => 0x18,
+            Self::Dmg | Self::Mgb => 0xAB,
+            _ => 0x00,
+        };
+        io.push((0xFF04, div));
+        io.push((0xFF26, if self.is_sgb() { 0xF0 } else { 0xF1 }));
+
+        if self.is_cgb() {
+            // SC has the fast clock bit here, which reads back set
+            io.extend([
+                (0xFF02, 0x7F), (0xFF46, 0x00), (0xFF4D, 0x7E), (0xFF4F, 0xFE), (0xFF55, 0xFF),
+                (0xFF56, 0x3E), (0xFF70, 0xF8),
+            ]);
+        }
+        else {
+            io.extend([(0xFF02, 0x7E), (0xFF46, 0xFF)]);
+        }
+        io
+    }
+}
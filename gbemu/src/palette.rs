@@ -0,0 +1,144 @@
+// Colours for the DMG's 4 shades
+//
+// The PPU leaves each pixel as a shade, already through BGP, OBP0 or OBP1, tagged with the
+// layer it came from. Turning that into RGBA is up to the screen: the DMG's is green, the
+// Pocket's grey and the Light's backlit teal. Each layer can have its own palette, as the CGB
+// does when running DMG games.
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+use wasm_bindgen::prelude::*;
+
+use crate::ppu::{Layer, LAYER_SHIFT};
+
+// 0xRRGGBB colours, from shade 0 to shade 3
+#[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), wasm_bindgen)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub lightest: u32,
+    pub light: u32,
+    pub dark: u32,
+    pub darkest: u32,
+}
+
+#[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), wasm_bindgen)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum PalettePreset {
+    #[default]
+    DmgGreen,
+    PocketGrey,
+    LightTeal,
+    // Okabe-Ito orange and blue, told apart by brightness as well as hue
+    HighContrast,
+}
+
+#[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), wasm_bindgen)]
+impl Palette {
+    #[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), wasm_bindgen(constructor))]
+    pub fn new(lightest: u32, light: u32, dark: u32, darkest: u32) -> Self {
+        Self { lightest, light, dark, darkest }
+    }
+
+    pub fn preset(preset: PalettePreset) -> Self {
+        match preset {
+            PalettePreset::DmgGreen => Self::new(0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F),
+            PalettePreset::PocketGrey => Self::new(0xC4CFA1, 0x8B956D, 0x4D533C, 0x1F1F1F),
+            PalettePreset::LightTeal => Self::new(0x00B581, 0x009A71, 0x00694A, 0x004F3B),
+            PalettePreset::HighContrast => Self::new(0xFFFFFF, 0xE69F00, 0x0072B2, 0x000000),
+        }
+    }
+}
+
+impl Palette {
+    fn rgba(self, shade: u8) -> [u8; 4] {
+        let colour = match shade & 0x03 {
+            0 => self.lightest,
+            1 => self.light,
+            2 => self.dark,
+            _ => self.darkest,
+        };
+        let [_, r, g, b] = colour.to_be_bytes();
+        [r, g, b, 0xFF]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::preset(PalettePreset::default())
+    }
+}
+
+// A palette for each layer
+#[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), wasm_bindgen)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Palettes {
+    pub bg: Palette,
+    pub obj0: Palette,
+    pub obj1: Palette,
+}
+
+#[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), wasm_bindgen)]
+impl Palettes {
+    #[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), wasm_bindgen(constructor))]
+    pub fn new(bg: Palette, obj0: Palette, obj1: Palette) -> Self {
+        Self { bg, obj0, obj1 }
+    }
+
+    // The same palette for everything, as on a DMG
+    pub fn uniform(palette: Palette) -> Self {
+        Self::new(palette, palette, palette)
+    }
+}
+
+impl Palettes {
+    pub fn layer(&self, layer: Layer) -> Palette {
+        match layer {
+            Layer::Bg => self.bg,
+            Layer::Obj0 => self.obj0,
+            Layer::Obj1 => self.obj1,
+        }
+    }
+
+    // A framebuffer pixel's colour
+    pub fn rgba(&self, pixel: u8) -> [u8; 4] {
+        let palette = match pixel >> LAYER_SHIFT {
+            1 => self.obj0,
+            2 => self.obj1,
+            _ => self.bg,
+        };
+        palette.rgba(pixel)
+    }
+
+    // Fill rgba with a framebuffer's colours, 4 bytes a pixel
+    pub fn convert(&self, framebuffer: &[u8], rgba: &mut [u8]) {
+        for (pixel, out) in framebuffer.iter().zip(rgba.chunks_exact_mut(4)) {
+            out.copy_from_slice(&self.rgba(*pixel));
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shades_to_rgba() {
+        let palette = Palette::preset(PalettePreset::HighContrast);
+        assert_eq!(palette.rgba(0), [0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(palette.rgba(1), [0xE6, 0x9F, 0x00, 0xFF]);
+        assert_eq!(palette.rgba(3), [0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(Palette::default(), Palette::preset(PalettePreset::DmgGreen));
+    }
+
+    #[test]
+    fn layers_tinted_separately() {
+        let red = Palette::new(0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000);
+        let palettes = Palettes::new(Palette::preset(PalettePreset::PocketGrey), red, Palette::new(0, 0, 0, 0x0000FF));
+        assert_eq!(palettes.layer(Layer::Obj0), red);
+
+        let framebuffer = [2, (Layer::Obj0 as u8) << LAYER_SHIFT | 1, (Layer::Obj1 as u8) << LAYER_SHIFT | 3];
+        let mut rgba = [0; 12];
+        palettes.convert(&framebuffer, &mut rgba);
+        assert_eq!(rgba, [0x4D, 0x53, 0x3C, 0xFF, 0xFF, 0x84, 0x84, 0xFF, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+}
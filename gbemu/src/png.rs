@@ -0,0 +1,136 @@
+// A minimal PNG encoder for RGBA images, without compression: the zlib stream uses stored
+// blocks only. Files are bigger than they need be, but it keeps the crate free of dependencies.
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+// Largest stored deflate block
+const MAX_BLOCK: usize = 0xFFFF;
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // Deflate, 32K window, no dictionary, check bits making the header a multiple of 31
+    let mut out = vec![0x78, 0x01];
+    let blocks = data.chunks(MAX_BLOCK).collect::<Vec<_>>();
+    if blocks.is_empty() {
+        out.extend([0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    for (i, block) in blocks.iter().enumerate() {
+        let len = block.len() as u16;
+        out.push((i == blocks.len() - 1) as u8);
+        out.extend(len.to_le_bytes());
+        out.extend((!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+// rgba holds width * height pixels, 4 bytes each, row by row from the top
+pub fn encode_rgba(width: usize, height: usize, rgba: &[u8]) -> Vec<u8> {
+    assert_eq!(rgba.len(), width * height * 4, "RGBA buffer doesn't match the image size");
+
+    let mut header = Vec::with_capacity(13);
+    header.extend((width as u32).to_be_bytes());
+    header.extend((height as u32).to_be_bytes());
+    // 8 bits per channel, RGBA, deflate, adaptive filtering, not interlaced
+    header.extend([8, 6, 0, 0, 0]);
+
+    // Every scanline starts with its filter type, none
+    let mut scanlines = Vec::with_capacity(height * (width * 4 + 1));
+    for row in rgba.chunks(width * 4).take(height) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(row);
+    }
+
+    let mut png = SIGNATURE.to_vec();
+    chunk(&mut png, b"IHDR", &header);
+    chunk(&mut png, b"IDAT", &zlib_stored(&scanlines));
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+// Blow each pixel up into a scale x scale square
+pub fn upscale(width: usize, rgba: &[u8], scale: usize) -> Vec<u8> {
+    let mut scaled = Vec::with_capacity(rgba.len() * scale * scale);
+    for row in rgba.chunks(width * 4) {
+        let mut line = Vec::with_capacity(row.len() * scale);
+        for pixel in row.chunks(4) {
+            for _ in 0..scale {
+                line.extend_from_slice(pixel);
+            }
+        }
+        for _ in 0..scale {
+            scaled.extend_from_slice(&line);
+        }
+    }
+    scaled
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub fn write(path: impl AsRef<std::path::Path>, width: usize, height: usize, rgba: &[u8]) -> std::io::Result<()> {
+    std::fs::write(path, encode_rgba(width, height, rgba))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn encodes_chunks() {
+        let png = encode_rgba(2, 1, &[0xFF, 0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF]);
+        assert_eq!(png[..8], SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(png[16..24], [0, 0, 0, 2, 0, 0, 0, 1]);
+        assert_eq!(png[png.len() - 8..], [b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]);
+
+        // Stored blocks split at 64K
+        let big = zlib_stored(&vec![0u8; MAX_BLOCK + 1]);
+        assert_eq!(big[2], 0x00);
+        assert_eq!(big[3 + 4 + MAX_BLOCK], 0x01);
+    }
+
+    #[test]
+    fn upscales() {
+        let rgba = [1, 1, 1, 1, 2, 2, 2, 2];
+        let scaled = upscale(2, &rgba, 2);
+        assert_eq!(scaled.len(), 32);
+        assert_eq!(scaled[..16], [1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2]);
+        assert_eq!(scaled[..16], scaled[16..]);
+        assert_eq!(upscale(2, &rgba, 1), rgba);
+    }
+}
@@ -0,0 +1,860 @@
+// LCD controller: the PPU's modes, LY/LYC and the VBlank and STAT interrupts
+//
+// A frame is 154 lines of 456 dots, 4 dots to the M-cycle. Lines 0-143 are visible, each one
+// an OAM scan (mode 2) for 80 dots, drawing (mode 3) for 172, then HBlank (mode 0) for the
+// rest. Lines 144-153 are VBlank (mode 1), which requests the VBlank interrupt as it starts.
+// STAT can interrupt in modes 0, 1 and 2, and while LY is LYC. The enabled sources are OR-ed
+// into one line and only its rising edge interrupts, so a source becoming active while another
+// still is goes unnoticed. On the DMG writing STAT enables every source for a moment, which
+// interrupts if any is active. LY reads 153 for only the first M-cycle of the last line, then
+// 0, so LYC=0 matches during line 153.
+//
+// Switching the LCD on starts line 0 a few dots late, with no OAM scan and reading as mode 0,
+// and the frame drawn isn't shown. Switched off it shows nothing, LY is held at 0 and STAT
+// reads mode 0. Switching off outside VBlank is said to damage a DMG's screen, so it's
+// logged.
+//
+// The framebuffer holds each pixel's shade (0-3), after BGP, OBP0 or OBP1, tagged with the
+// layer whose palette it went through. By default each visible line is rendered whole as
+// drawing ends, with mode 3 a fixed length. The FIFO renderer instead draws dot by dot as the
+// hardware does, for raster effects and mode 3 timing, at some cost in speed. The
+// background is a 32x32 tile map scrolled by SCX/SCY, wrapping around, and the window is a
+// second map drawn over it from WX-7, WY. The window has its own line counter, which only
+// advances on lines it is actually drawn on.
+//
+// While drawing the PPU has VRAM to itself, and OAM too from the start of the OAM scan: the
+// CPU and DMA read 0xFF and their writes are lost.
+//
+// Objects (sprites) are chosen as OAM scan ends: the first 10 in OAM order on the line,
+// whatever their X. Where they overlap, the one with the lowest X wins, then the lowest OAM
+// index, as on the DMG. An object's colour 0 is transparent, and with its priority bit set
+// it only shows over background colour 0.
+
+mod fifo;
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+use wasm_bindgen::prelude::*;
+
+use crate::log as console_log;
+use fifo::Fifo;
+
+pub const LCDC: u16 = 0xFF40;
+pub const STAT: u16 = 0xFF41;
+pub const SCY: u16 = 0xFF42;
+pub const SCX: u16 = 0xFF43;
+pub const LY: u16 = 0xFF44;
+pub const LYC: u16 = 0xFF45;
+pub const BGP: u16 = 0xFF47;
+pub const OBP0: u16 = 0xFF48;
+pub const OBP1: u16 = 0xFF49;
+pub const WY: u16 = 0xFF4A;
+pub const WX: u16 = 0xFF4B;
+
+pub const WIDTH: usize = 160;
+pub const HEIGHT: usize = 144;
+
+const VRAM_START: u16 = 0x8000;
+const VRAM_SIZE: usize = 0x2000;
+const OAM_START: u16 = 0xFE00;
+const OAM_SIZE: usize = 0xA0;
+
+const OBJECTS_PER_LINE: usize = 10;
+
+// Framebuffer pixels hold the layer above the shade
+pub const LAYER_SHIFT: u8 = 2;
+
+const LCDC_ENABLE: u8 = 0x80;
+const LCDC_WINDOW_MAP: u8 = 0x40;
+const LCDC_WINDOW: u8 = 0x20;
+const LCDC_UNSIGNED_TILES: u8 = 0x10;
+const LCDC_BG_MAP: u8 = 0x08;
+const LCDC_TALL_OBJECTS: u8 = 0x04;
+const LCDC_OBJECTS: u8 = 0x02;
+const LCDC_BG: u8 = 0x01;
+
+const OBJECT_BEHIND_BG: u8 = 0x80;
+const OBJECT_Y_FLIP: u8 = 0x40;
+const OBJECT_X_FLIP: u8 = 0x20;
+const OBJECT_OBP1: u8 = 0x10;
+
+const STAT_COINCIDENCE: u8 = 0x04;
+const STAT_HBLANK: u8 = 0x08;
+const STAT_VBLANK: u8 = 0x10;
+const STAT_OAM: u8 = 0x20;
+const STAT_LYC: u8 = 0x40;
+const STAT_WRITABLE: u8 = STAT_HBLANK | STAT_VBLANK | STAT_OAM | STAT_LYC;
+
+pub const DOTS_PER_M_CYCLE: u16 = 4;
+const DOTS_PER_LINE: u16 = 456;
+const OAM_SCAN_DOTS: u16 = 80;
+const DRAWING_DOTS: u16 = 172;
+// Where line 0 starts when the LCD is switched on
+const LCD_ON_DOT: u16 = 4;
+// How long LY reads 153 before wrapping to 0
+const LAST_LINE_DOTS: u16 = 4;
+const VISIBLE_LINES: u8 = 144;
+const LINES: u8 = 154;
+// Past this the window is entirely off screen
+const MAX_WX: u8 = 166;
+
+// What to do when the CPU reaches for VRAM or OAM while the PPU has it locked. The access is
+// blocked whatever the policy; logging or breaking shows up code that only works on emulators
+// that don't lock.
+#[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), wasm_bindgen)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum LockedAccessPolicy {
+    #[default]
+    Ignore,
+    Log,
+    // Stop running, leaving the access to be picked up with Emulator::take_locked_access
+    Break,
+}
+
+// A blocked CPU access, with the value if it was a write
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LockedAccess {
+    pub addr: u16,
+    pub value: Option<u8>,
+    pub mode: Mode,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+#[cfg_attr(all(target_arch = "wasm32", target_os = "unknown"), wasm_bindgen)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Renderer {
+    // A line at a time, fast
+    #[default]
+    Scanline,
+    // Dot by dot through the pixel FIFOs, accurate
+    Fifo,
+}
+
+// Whose palette a framebuffer pixel is drawn with
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Layer {
+    Bg = 0,
+    Obj0 = 1,
+    Obj1 = 2,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Object {
+    y: u8,
+    x: u8,
+    tile: u8,
+    attributes: u8,
+}
+
+// Interrupts to request after a step
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Requests {
+    pub vblank: bool,
+    pub stat: bool,
+}
+
+pub struct Ppu {
+    lcdc: u8,
+    // Only the interrupt source bits, the rest are worked out on reading
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    mode: Mode,
+    // Dot within the current line
+    dot: u16,
+    vram: Vec<u8>,
+    oam: Vec<u8>,
+    // Objects on the current line, by priority
+    objects: Vec<Object>,
+    // Set once LY has matched WY this frame, after which the window can be drawn
+    window_triggered: bool,
+    window_line: u8,
+    framebuffer: Vec<u8>,
+    renderer: Renderer,
+    // The line being drawn, with the FIFO renderer
+    fifo: Option<Fifo>,
+    // The OR of the enabled STAT sources
+    stat_line: bool,
+    // A STAT interrupt from a register write, requested on the next step
+    stat_requested: bool,
+    stat_write_bug: bool,
+    // On line 0 just after switching the LCD on, before drawing starts
+    starting: bool,
+    // Nothing on screen, as the LCD is off or on its first frame
+    blank: bool,
+    // The frame being drawn is the first since the LCD was switched on
+    hidden_frame: bool,
+    unsafe_lcd_off: bool,
+    // VBlanks since power on
+    frames: u64,
+}
+
+// What the screen shows while blank
+static BLANK: [u8; WIDTH * HEIGHT] = [0; WIDTH * HEIGHT];
+
+impl Ppu {
+    // LCD off, as at power on
+    pub fn new(stat_write_bug: bool) -> Self {
+        Self {
+            lcdc: 0,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            mode: Mode::HBlank,
+            dot: 0,
+            vram: vec![0; VRAM_SIZE],
+            oam: vec![0; OAM_SIZE],
+            objects: Vec::with_capacity(OBJECTS_PER_LINE),
+            window_triggered: false,
+            window_line: 0,
+            framebuffer: vec![0; WIDTH * HEIGHT],
+            renderer: Renderer::default(),
+            fifo: None,
+            stat_line: false,
+            stat_requested: false,
+            stat_write_bug,
+            starting: false,
+            blank: true,
+            hidden_frame: false,
+            unsafe_lcd_off: false,
+            frames: 0,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.lcdc & LCDC_ENABLE != 0
+    }
+
+    // Takes effect from the next line drawn
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+    }
+
+    // 160x144 pixels, row by row from the top, each a shade with the Layer above it
+    pub fn framebuffer(&self) -> &[u8] {
+        if self.blank { &BLANK } else { &self.framebuffer }
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    // Whether the LCD has ever been switched off outside VBlank
+    pub fn unsafe_lcd_off(&self) -> bool {
+        self.unsafe_lcd_off
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn vram_locked(&self) -> bool {
+        self.mode == Mode::Drawing
+    }
+
+    pub fn oam_locked(&self) -> bool {
+        matches!(self.mode, Mode::OamScan | Mode::Drawing)
+    }
+
+    pub fn read_vram(&self, addr: u16) -> u8 {
+        self.vram[(addr - VRAM_START) as usize]
+    }
+
+    pub fn write_vram(&mut self, addr: u16, value: u8) {
+        self.vram[(addr - VRAM_START) as usize] = value;
+    }
+
+    pub fn read_oam(&self, addr: u16) -> u8 {
+        self.oam[(addr - OAM_START) as usize]
+    }
+
+    pub fn write_oam(&mut self, addr: u16, value: u8) {
+        self.oam[(addr - OAM_START) as usize] = value;
+    }
+
+    fn coincidence(&self) -> bool {
+        self.ly == self.lyc
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            LCDC => self.lcdc,
+            STAT => {
+                let coincidence = if self.coincidence() { STAT_COINCIDENCE } else { 0 };
+                0x80 | self.stat | coincidence | self.mode as u8
+            }
+            SCY => self.scy,
+            SCX => self.scx,
+            LY => self.ly,
+            LYC => self.lyc,
+            BGP => self.bgp,
+            OBP0 => self.obp0,
+            OBP1 => self.obp1,
+            WY => self.wy,
+            _ => self.wx,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            LCDC => {
+                let was_enabled = self.enabled();
+                self.lcdc = value;
+                if was_enabled && !self.enabled() {
+                    if self.mode != Mode::VBlank {
+                        self.unsafe_lcd_off = true;
+                        console_log(format!("LCD switched off outside VBlank, on line {}", self.ly).as_str());
+                    }
+                    self.ly = 0;
+                    self.dot = 0;
+                    self.fifo = None;
+                    self.mode = Mode::HBlank;
+                    self.stat_line = false;
+                    self.blank = true;
+                }
+                else if !was_enabled && self.enabled() {
+                    self.dot = LCD_ON_DOT;
+                    self.start_frame();
+                    self.starting = true;
+                    self.hidden_frame = true;
+                }
+            }
+            STAT => {
+                if self.stat_write_bug && self.stat_sources(STAT_WRITABLE) {
+                    self.stat_requested |= !self.stat_line;
+                    self.stat_line = true;
+                }
+                self.stat = value & STAT_WRITABLE;
+                self.stat_requested |= self.update_stat_line();
+            }
+            // Read only
+            LY => (),
+            LYC => {
+                self.lyc = value;
+                self.stat_requested |= self.update_stat_line();
+            }
+            _ => self.restore(addr, value),
+        }
+    }
+
+    // Set a register as the boot ROM left it, without any of the side effects of writing it
+    pub fn restore(&mut self, addr: u16, value: u8) {
+        match addr {
+            LCDC => {
+                self.lcdc = value;
+                self.blank = !self.enabled();
+            }
+            STAT => {
+                self.stat = value & STAT_WRITABLE;
+                self.mode = match value & 0x03 {
+                    0 => Mode::HBlank,
+                    1 => Mode::VBlank,
+                    2 => Mode::OamScan,
+                    _ => Mode::Drawing,
+                };
+            }
+            SCY => self.scy = value,
+            SCX => self.scx = value,
+            // From the start of the line
+            LY => {
+                self.ly = value;
+                self.dot = 0;
+            }
+            LYC => self.lyc = value,
+            BGP => self.bgp = value,
+            OBP0 => self.obp0 = value,
+            OBP1 => self.obp1 = value,
+            WY => self.wy = value,
+            _ => self.wx = value,
+        }
+    }
+
+    fn start_frame(&mut self) {
+        self.window_triggered = false;
+        self.window_line = 0;
+    }
+
+    // Colour number of a pixel in the tile at (column, row) of a tile map
+    fn tile_pixel(&self, map: u16, column: u8, row: u8, x: u8, y: u8) -> u8 {
+        let index = self.read_vram(map + (row as u16 % 32) * 32 + column as u16 % 32);
+        let tile = if self.lcdc & LCDC_UNSIGNED_TILES != 0 {
+            0x8000 + index as u16 * 16
+        }
+        else {
+            0x9000u16.wrapping_add((index as i8 as i16 * 16) as u16)
+        };
+        self.pixel(tile, x % 8, y % 8)
+    }
+
+    fn pixel(&self, tile: u16, x: u8, y: u8) -> u8 {
+        let addr = tile + y as u16 * 2;
+        let bit = 7 - x;
+        let low = (self.read_vram(addr) >> bit) & 1;
+        let high = (self.read_vram(addr + 1) >> bit) & 1;
+        high << 1 | low
+    }
+
+    fn object_height(&self) -> u8 {
+        if self.lcdc & LCDC_TALL_OBJECTS != 0 { 16 } else { 8 }
+    }
+
+    // A framebuffer pixel for a colour number, through the layer's palette
+    fn shade(&self, colour: u8, layer: Layer) -> u8 {
+        let palette = match layer {
+            Layer::Bg => self.bgp,
+            Layer::Obj0 => self.obp0,
+            Layer::Obj1 => self.obp1,
+        };
+        (layer as u8) << LAYER_SHIFT | (palette >> (colour * 2)) & 0x03
+    }
+
+    fn window_enabled(&self) -> bool {
+        self.lcdc & LCDC_WINDOW != 0 && self.window_triggered && self.wx <= MAX_WX
+    }
+
+    fn start_line(&mut self) {
+        self.window_triggered |= self.ly == self.wy;
+        self.scan_oam();
+    }
+
+    fn scan_oam(&mut self) {
+        let height = self.object_height();
+        // Y is the bottom of a 16 pixel tall object, plus one
+        let line = self.ly.wrapping_add(16);
+        self.objects = self.oam.chunks(4)
+            .map(|entry| Object { y: entry[0], x: entry[1], tile: entry[2], attributes: entry[3] })
+            .filter(|object| line >= object.y && line < object.y.saturating_add(height))
+            .take(OBJECTS_PER_LINE)
+            .collect();
+        // Stable, so OAM order still decides between equal X
+        self.objects.sort_by_key(|object| object.x);
+    }
+
+    // The colour number and layer of the object showing at x, if any, and whether it's behind
+    // background colours 1-3
+    fn object_pixel(&self, x: u8) -> Option<(u8, Layer, bool)> {
+        let height = self.object_height();
+        // X is the right edge plus one, so 0 and 168 onwards are off screen
+        let screen_x = x + 8;
+        self.objects.iter()
+            .filter(|object| screen_x >= object.x && screen_x < object.x.saturating_add(8))
+            .find_map(|object| {
+                let mut column = screen_x - object.x;
+                let mut row = self.ly.wrapping_add(16).wrapping_sub(object.y);
+                if object.attributes & OBJECT_X_FLIP != 0 {
+                    column = 7 - column;
+                }
+                if object.attributes & OBJECT_Y_FLIP != 0 {
+                    row = height - 1 - row;
+                }
+                let tile = if height == 16 { object.tile & 0xFE } else { object.tile };
+                let colour = self.pixel(VRAM_START + tile as u16 * 16, column, row % 16);
+                let layer = if object.attributes & OBJECT_OBP1 != 0 { Layer::Obj1 } else { Layer::Obj0 };
+                let behind = object.attributes & OBJECT_BEHIND_BG != 0;
+                (colour != 0).then_some((colour, layer, behind))
+            })
+    }
+
+    fn render_line(&mut self) {
+        let window = self.window_enabled();
+        let bg_map = if self.lcdc & LCDC_BG_MAP != 0 { 0x9C00 } else { 0x9800 };
+        let window_map = if self.lcdc & LCDC_WINDOW_MAP != 0 { 0x9C00 } else { 0x9800 };
+        let mut window_drawn = false;
+
+        for x in 0..WIDTH as u8 {
+            // On the DMG clearing LCDC bit 0 blanks both the background and the window
+            let colour = if self.lcdc & LCDC_BG == 0 {
+                0
+            }
+            else if window && x as u16 + 7 >= self.wx as u16 {
+                window_drawn = true;
+                let window_x = x + 7 - self.wx;
+                self.tile_pixel(window_map, window_x / 8, self.window_line / 8, window_x, self.window_line)
+            }
+            else {
+                let bg_x = x.wrapping_add(self.scx);
+                let bg_y = self.ly.wrapping_add(self.scy);
+                self.tile_pixel(bg_map, bg_x / 8, bg_y / 8, bg_x, bg_y)
+            };
+
+            let mut pixel = (colour, Layer::Bg);
+            if self.lcdc & LCDC_OBJECTS != 0 {
+                match self.object_pixel(x) {
+                    Some((_, _, true)) if colour != 0 => (),
+                    Some((object, layer, _)) => pixel = (object, layer),
+                    None => (),
+                }
+            }
+            self.framebuffer[self.ly as usize * WIDTH + x as usize] = self.shade(pixel.0, pixel.1);
+        }
+
+        if window_drawn {
+            self.window_line += 1;
+        }
+    }
+
+    fn enter(&mut self, mode: Mode, requests: &mut Requests) {
+        if mode == Mode::Drawing {
+            self.start_line();
+            if self.renderer == Renderer::Fifo {
+                self.fifo = Some(Fifo::new(self));
+            }
+        }
+        // Whichever renderer the line was started with draws it
+        else if mode == Mode::HBlank && self.fifo.take().is_none() {
+            self.render_line();
+        }
+        else if mode == Mode::VBlank {
+            self.start_frame();
+            self.blank = std::mem::take(&mut self.hidden_frame);
+            self.frames += 1;
+        }
+        self.mode = mode;
+        requests.vblank |= mode == Mode::VBlank;
+    }
+
+    // Whether any of the given STAT interrupt sources is active
+    fn stat_sources(&self, sources: u8) -> bool {
+        if !self.enabled() {
+            return false;
+        }
+        let mode = match self.mode {
+            Mode::HBlank if self.starting => 0,
+            Mode::HBlank => STAT_HBLANK,
+            Mode::VBlank => STAT_VBLANK,
+            Mode::OamScan => STAT_OAM,
+            Mode::Drawing => 0,
+        };
+        sources & mode != 0 || sources & STAT_LYC != 0 && self.coincidence()
+    }
+
+    // Returns true on the STAT line's rising edge
+    fn update_stat_line(&mut self) -> bool {
+        let line = self.stat_sources(self.stat);
+        let rising = line && !self.stat_line;
+        self.stat_line = line;
+        rising
+    }
+
+    fn next_line(&mut self, requests: &mut Requests) {
+        self.dot = 0;
+        // LY has already wrapped if this was line 153
+        self.ly = match self.ly {
+            0 if self.mode == Mode::VBlank => 0,
+            ly => ly + 1,
+        };
+
+        if self.ly < VISIBLE_LINES {
+            self.enter(Mode::OamScan, requests);
+        }
+        else if self.ly == VISIBLE_LINES {
+            self.enter(Mode::VBlank, requests);
+        }
+    }
+
+    fn dot(&mut self, requests: &mut Requests) {
+        self.dot += 1;
+        match self.mode {
+            Mode::HBlank if self.starting && self.dot == OAM_SCAN_DOTS => {
+                self.starting = false;
+                self.enter(Mode::Drawing, requests);
+            }
+            Mode::OamScan if self.dot == OAM_SCAN_DOTS => self.enter(Mode::Drawing, requests),
+            Mode::Drawing if self.fifo.is_some() => self.fifo_dot(requests),
+            Mode::Drawing if self.dot == OAM_SCAN_DOTS + DRAWING_DOTS => self.enter(Mode::HBlank, requests),
+            Mode::VBlank if self.ly == LINES - 1 && self.dot == LAST_LINE_DOTS => self.ly = 0,
+            _ if self.dot == DOTS_PER_LINE => self.next_line(requests),
+            _ => (),
+        }
+        requests.stat |= self.update_stat_line();
+    }
+
+    // Run the FIFO a dot, moving on to HBlank once the line is drawn
+    fn fifo_dot(&mut self, requests: &mut Requests) {
+        let Some(mut fifo) = self.fifo.take() else {
+            return;
+        };
+        let done = fifo.dot(self);
+        self.fifo = Some(fifo);
+        if done {
+            self.enter(Mode::HBlank, requests);
+        }
+    }
+
+    // Advance one M-cycle. Nothing happens with the LCD off.
+    // Advance a CPU M-cycle's worth of dots, which is half as many in double speed
+    pub fn step(&mut self, dots: u16) -> Requests {
+        let mut requests = Requests { stat: std::mem::take(&mut self.stat_requested), ..Requests::default() };
+        if self.enabled() {
+            for _ in 0..dots {
+                self.dot(&mut requests);
+            }
+        }
+        requests
+    }
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    const M_CYCLES_PER_LINE: u32 = (DOTS_PER_LINE / DOTS_PER_M_CYCLE) as u32;
+
+    fn run(ppu: &mut Ppu, m_cycles: u32) -> Vec<Requests> {
+        (0..m_cycles).map(|_| ppu.step(DOTS_PER_M_CYCLE)).filter(|requests| *requests != Requests::default()).collect()
+    }
+
+    #[test]
+    fn modes_and_lines() {
+        let mut ppu = Ppu::new(false);
+        run(&mut ppu, 100);
+        assert_eq!(ppu.read(LY), 0);
+
+        // Line 0 starts late, without an OAM scan, and is a M-cycle short
+        ppu.write(LCDC, LCDC_ENABLE);
+        assert_eq!(ppu.read(STAT) & 0x03, 0);
+        assert!(!ppu.oam_locked());
+        run(&mut ppu, 19);
+        assert_eq!(ppu.mode, Mode::Drawing);
+        run(&mut ppu, 43);
+        assert_eq!(ppu.mode, Mode::HBlank);
+        run(&mut ppu, M_CYCLES_PER_LINE - 63);
+        assert_eq!((ppu.read(LY), ppu.mode), (1, Mode::OamScan));
+
+        run(&mut ppu, 20);
+        assert_eq!(ppu.mode, Mode::Drawing);
+        run(&mut ppu, 43);
+        assert_eq!(ppu.mode, Mode::HBlank);
+        run(&mut ppu, M_CYCLES_PER_LINE - 63);
+        assert_eq!((ppu.read(LY), ppu.mode), (2, Mode::OamScan));
+
+        // VBlank once per frame, at line 144
+        let requests = run(&mut ppu, M_CYCLES_PER_LINE * 142);
+        assert_eq!(requests, [Requests { vblank: true, stat: false }]);
+        assert_eq!((ppu.read(LY), ppu.mode), (144, Mode::VBlank));
+
+        // LY reads 153 for a M-cycle, then 0 for the rest of the line
+        run(&mut ppu, M_CYCLES_PER_LINE * 9 + 1);
+        assert_eq!(ppu.read(LY), 0);
+        assert_eq!(ppu.mode, Mode::VBlank);
+        run(&mut ppu, M_CYCLES_PER_LINE - 1);
+        assert_eq!((ppu.read(LY), ppu.mode), (0, Mode::OamScan));
+
+        ppu.write(LCDC, 0);
+        assert_eq!((ppu.read(LY), ppu.mode), (0, Mode::HBlank));
+    }
+
+    // Tile 1 solid colour 3, placed at the top left of the map at 0x9800 and 0x9C00
+    pub(crate) fn tiles(lcdc: u8) -> Ppu {
+        let mut ppu = Ppu::new(false);
+        let tile = if lcdc & LCDC_UNSIGNED_TILES != 0 { 0x8010 } else { 0x9010 };
+        for addr in tile..tile + 16 {
+            ppu.write_vram(addr, 0xFF);
+        }
+        ppu.write_vram(0x9800, 0x01);
+        ppu.write_vram(0x9C00, 0x01);
+        ppu.write(LCDC, lcdc);
+        for palette in [BGP, OBP0, OBP1] {
+            ppu.write(palette, 0xE4);
+        }
+        ppu
+    }
+
+    fn line(ppu: &mut Ppu) -> Vec<u8> {
+        ppu.start_line();
+        ppu.render_line();
+        ppu.framebuffer[ppu.ly as usize * WIDTH..][..WIDTH].to_vec()
+    }
+
+    fn solid(start: usize, end: usize) -> Vec<u8> {
+        (0..WIDTH).map(|x| if (start..end).contains(&x) { 3 } else { 0 }).collect()
+    }
+
+    #[test]
+    fn background_scrolls_and_wraps() {
+        for lcdc in [LCDC_ENABLE | LCDC_UNSIGNED_TILES | LCDC_BG, LCDC_ENABLE | LCDC_BG] {
+            let mut ppu = tiles(lcdc);
+            assert_eq!(line(&mut ppu), solid(0, 8));
+            ppu.write(SCX, 4);
+            assert_eq!(line(&mut ppu), solid(0, 4));
+            ppu.write(SCX, 252);
+            assert_eq!(line(&mut ppu), solid(4, 12));
+            ppu.write(SCY, 250);
+            assert_eq!(line(&mut ppu), solid(0, 0));
+        }
+
+        let mut ppu = tiles(LCDC_ENABLE | LCDC_UNSIGNED_TILES);
+        assert_eq!(line(&mut ppu), solid(0, 0));
+    }
+
+    #[test]
+    fn window_line_counter() {
+        let mut ppu = tiles(LCDC_ENABLE | LCDC_WINDOW_MAP | LCDC_WINDOW | LCDC_UNSIGNED_TILES | LCDC_BG);
+        ppu.write(SCX, 8);
+        ppu.write(WY, 2);
+        ppu.write(WX, 87);
+        assert_eq!(line(&mut ppu), solid(0, 0));
+
+        // Moved off screen for a line, which doesn't count
+        ppu.ly = 2;
+        ppu.write(WX, 200);
+        line(&mut ppu);
+        ppu.write(WX, 87);
+        for ly in 3..11 {
+            ppu.ly = ly;
+            assert_eq!(line(&mut ppu), solid(80, 88));
+        }
+        ppu.ly = 11;
+        assert_eq!(line(&mut ppu), solid(0, 0));
+        assert_eq!(ppu.window_line, 9);
+    }
+
+    pub(crate) fn object(ppu: &mut Ppu, index: u16, y: u8, x: u8, tile: u8, attributes: u8) {
+        for (i, byte) in [y, x, tile, attributes].iter().enumerate() {
+            ppu.write_oam(OAM_START + index * 4 + i as u16, *byte);
+        }
+    }
+
+    #[test]
+    fn object_priority_and_limit() {
+        let mut ppu = tiles(LCDC_ENABLE | LCDC_UNSIGNED_TILES | LCDC_OBJECTS | LCDC_BG);
+        // Tile 3 solid colour 1
+        for addr in (0x8030..0x8040).step_by(2) {
+            ppu.write_vram(addr, 0xFF);
+        }
+        let (obj0, obj1) = ((Layer::Obj0 as u8) << LAYER_SHIFT | 1, (Layer::Obj1 as u8) << LAYER_SHIFT | 1);
+
+        // Lower X wins, then lower OAM index
+        object(&mut ppu, 0, 16, 20, 3, OBJECT_OBP1);
+        object(&mut ppu, 1, 16, 16, 3, 0);
+        object(&mut ppu, 2, 16, 8, 3, OBJECT_BEHIND_BG);
+        object(&mut ppu, 3, 16, 40, 3, OBJECT_OBP1);
+        object(&mut ppu, 4, 16, 40, 3, 0);
+        // Off screen at X=0 but still one of the 10, so the 11th isn't drawn
+        object(&mut ppu, 5, 16, 0, 3, 0);
+        for index in 6..11 {
+            object(&mut ppu, index, 16, 60 + 8 * index as u8, 3, 0);
+        }
+
+        let pixels = line(&mut ppu);
+        assert_eq!(pixels[..8], [3; 8]);
+        assert_eq!(pixels[8..16], [obj0; 8]);
+        assert_eq!(pixels[16..20], [obj1; 4]);
+        assert_eq!(pixels[32..40], [obj1; 8]);
+        assert_eq!(pixels[100..132], [obj0; 32]);
+        assert_eq!(pixels[132..140], [0; 8]);
+
+        ppu.ly = 8;
+        assert_eq!(line(&mut ppu), solid(0, 0));
+        ppu.ly = 0;
+        ppu.write(LCDC, LCDC_ENABLE | LCDC_UNSIGNED_TILES | LCDC_BG);
+        assert_eq!(line(&mut ppu), solid(0, 8));
+    }
+
+    #[test]
+    fn tall_and_flipped_objects() {
+        let mut ppu = tiles(LCDC_ENABLE | LCDC_TALL_OBJECTS | LCDC_OBJECTS);
+        // Only the top left pixel of tile 2 is set
+        ppu.write_vram(0x8020, 0x80);
+        let set = |x: usize| (0..WIDTH).map(|i| if i == x { (Layer::Obj0 as u8) << LAYER_SHIFT | 1 } else { 0 }).collect::<Vec<_>>();
+
+        // The low bit of the tile index is ignored for 8x16 objects
+        object(&mut ppu, 0, 16, 8, 3, 0);
+        assert_eq!(line(&mut ppu), set(0));
+        object(&mut ppu, 0, 16, 8, 3, OBJECT_X_FLIP);
+        assert_eq!(line(&mut ppu), set(7));
+        object(&mut ppu, 0, 16, 8, 3, OBJECT_Y_FLIP);
+        assert_eq!(line(&mut ppu), solid(0, 0));
+        ppu.ly = 15;
+        assert_eq!(line(&mut ppu), set(0));
+        ppu.ly = 16;
+        assert_eq!(line(&mut ppu), solid(0, 0));
+    }
+
+    #[test]
+    fn stat_interrupts() {
+        let mut ppu = Ppu::new(false);
+        ppu.write(LYC, 2);
+        ppu.write(STAT, STAT_LYC);
+        ppu.write(LCDC, LCDC_ENABLE);
+        assert_eq!(ppu.read(STAT) & STAT_COINCIDENCE, 0);
+        let requests = run(&mut ppu, M_CYCLES_PER_LINE * 154);
+        assert_eq!(requests.iter().filter(|requests| requests.stat).count(), 1);
+        assert_eq!(run(&mut ppu, M_CYCLES_PER_LINE * 2).len(), 1);
+        assert_ne!(ppu.read(STAT) & STAT_COINCIDENCE, 0);
+
+        // One HBlank per visible line
+        ppu.write(STAT, STAT_HBLANK);
+        let requests = run(&mut ppu, M_CYCLES_PER_LINE * 154);
+        assert_eq!(requests.iter().filter(|requests| requests.stat).count(), 144);
+    }
+
+    #[test]
+    fn stat_line_blocking_and_write_bug() {
+        // Writing STAT on the DMG interrupts while LY matches LYC, even with LYC disabled
+        let mut ppu = Ppu::new(true);
+        ppu.write(LCDC, LCDC_ENABLE);
+        ppu.write(STAT, STAT_HBLANK | STAT_OAM);
+        assert!(ppu.step(DOTS_PER_M_CYCLE).stat);
+
+        // Mode 0 runs into mode 2 without the line dropping, so only HBlank interrupts until the
+        // next frame's first OAM scan
+        let requests = run(&mut ppu, M_CYCLES_PER_LINE * 154 - 1);
+        assert_eq!(requests.iter().filter(|requests| requests.stat).count(), 145);
+
+        // Elsewhere only enabling an active source does
+        let mut ppu = Ppu::new(false);
+        ppu.write(LCDC, LCDC_ENABLE);
+        ppu.write(STAT, STAT_HBLANK);
+        assert!(!ppu.step(DOTS_PER_M_CYCLE).stat);
+        ppu.write(STAT, STAT_LYC);
+        assert!(ppu.step(DOTS_PER_M_CYCLE).stat);
+
+        // LYC=0 matches on line 153, as soon as LY wraps
+        run(&mut ppu, M_CYCLES_PER_LINE * 153 - 3);
+        assert_eq!(ppu.read(LY), 153);
+        assert!(ppu.step(DOTS_PER_M_CYCLE).stat);
+        assert_eq!(ppu.read(LY), 0);
+    }
+
+    #[test]
+    fn lcd_on_and_off() {
+        let mut ppu = tiles(LCDC_ENABLE | LCDC_UNSIGNED_TILES | LCDC_BG);
+        run(&mut ppu, M_CYCLES_PER_LINE * 145);
+        assert_eq!(ppu.mode, Mode::VBlank);
+        assert_eq!(ppu.framebuffer()[0], 0);
+        run(&mut ppu, M_CYCLES_PER_LINE * 154);
+        assert_eq!(ppu.framebuffer()[0], 3);
+
+        ppu.write(LCDC, 0);
+        assert!(!ppu.unsafe_lcd_off());
+        assert_eq!(ppu.framebuffer()[0], 0);
+        assert_eq!((ppu.read(LY), ppu.read(STAT) & 0x03), (0, 0));
+
+        ppu.write(LCDC, LCDC_ENABLE);
+        run(&mut ppu, 30);
+        ppu.write(LCDC, 0);
+        assert!(ppu.unsafe_lcd_off());
+    }
+}
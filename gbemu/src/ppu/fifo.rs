@@ -0,0 +1,335 @@
+// Pixel FIFO renderer
+//
+// Mode 3 runs dot by dot as on hardware. A fetcher reads 8 pixels of background or window from
+// VRAM in three steps of 2 dots (tile number, low byte, high byte), then pushes them into the
+// background FIFO as soon as it's empty. Every dot the FIFO has pixels, one is shifted out to
+// the LCD, mixed with the object FIFO through the palettes and LCDC as they are at that dot.
+//
+// A line starts with a fetch whose pixels are thrown away, then drops SCX % 8 pixels for the
+// fine scroll. Reaching the window empties the FIFO and restarts the fetcher on the window map.
+// Reaching an object stops pixels shifting out until the fetcher has finished its tile, then
+// takes 6 dots to fetch the object's row into the object FIFO. Each of these lengthens mode 3
+// past the 172 dots of a plain line.
+
+use std::collections::VecDeque;
+
+use super::{
+    Layer, Ppu, LCDC_BG, LCDC_BG_MAP, LCDC_OBJECTS, LCDC_UNSIGNED_TILES, LCDC_WINDOW_MAP,
+    OBJECT_BEHIND_BG, OBJECT_OBP1, OBJECT_X_FLIP, OBJECT_Y_FLIP, VRAM_START, WIDTH,
+};
+
+const DOTS_PER_STEP: u8 = 2;
+const DISCARDED_FETCH_DOTS: u8 = 6;
+const OBJECT_FETCH_DOTS: u8 = 6;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Step {
+    Tile,
+    Low,
+    High,
+    Push,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct ObjectPixel {
+    colour: u8,
+    layer: Layer,
+    behind: bool,
+}
+
+pub struct Fifo {
+    bg: VecDeque<u8>,
+    objects: VecDeque<ObjectPixel>,
+    step: Step,
+    step_dots: u8,
+    // Tile column the fetcher is on, counted from the left of the screen or the window
+    fetch_x: u8,
+    tile: u8,
+    low: u8,
+    high: u8,
+    // Next pixel on the LCD
+    x: u8,
+    // Pixels still to drop before any reach the LCD
+    discard: u8,
+    window: bool,
+    // Dots left of the discarded first fetch
+    warm_up: u8,
+    // The next of the line's objects to fetch, and the dots left fetching it
+    next_object: usize,
+    object_fetch: Option<u8>,
+}
+
+impl Fifo {
+    pub fn new(ppu: &Ppu) -> Self {
+        Self {
+            bg: VecDeque::with_capacity(16),
+            objects: VecDeque::with_capacity(8),
+            step: Step::Tile,
+            step_dots: 0,
+            fetch_x: 0,
+            tile: 0,
+            low: 0,
+            high: 0,
+            x: 0,
+            discard: ppu.scx % 8,
+            window: false,
+            warm_up: DISCARDED_FETCH_DOTS,
+            next_object: 0,
+            object_fetch: None,
+        }
+    }
+
+    fn row(&self, ppu: &Ppu) -> u8 {
+        if self.window { ppu.window_line } else { ppu.ly.wrapping_add(ppu.scy) }
+    }
+
+    fn tile_addr(&self, ppu: &Ppu) -> u16 {
+        let base = if ppu.lcdc & LCDC_UNSIGNED_TILES != 0 {
+            VRAM_START + self.tile as u16 * 16
+        }
+        else {
+            0x9000u16.wrapping_add((self.tile as i8 as i16 * 16) as u16)
+        };
+        base + (self.row(ppu) % 8) as u16 * 2
+    }
+
+    fn fetch(&mut self, ppu: &Ppu) {
+        if self.step == Step::Push {
+            if self.bg.is_empty() {
+                let (low, high) = (self.low, self.high);
+                self.bg.extend((0..8).rev().map(|bit| ((high >> bit) & 1) << 1 | (low >> bit) & 1));
+                self.fetch_x = self.fetch_x.wrapping_add(1);
+                self.step = Step::Tile;
+            }
+            return;
+        }
+
+        self.step_dots += 1;
+        if self.step_dots < DOTS_PER_STEP {
+            return;
+        }
+        self.step_dots = 0;
+        self.step = match self.step {
+            Step::Tile => {
+                let (map, column) = if self.window {
+                    (if ppu.lcdc & LCDC_WINDOW_MAP != 0 { 0x9C00 } else { 0x9800 }, self.fetch_x)
+                }
+                else {
+                    (if ppu.lcdc & LCDC_BG_MAP != 0 { 0x9C00 } else { 0x9800 }, ppu.scx / 8 + self.fetch_x)
+                };
+                let row = self.row(ppu) / 8;
+                self.tile = ppu.read_vram(map + (row as u16 % 32) * 32 + column as u16 % 32);
+                Step::Low
+            }
+            Step::Low => {
+                self.low = ppu.read_vram(self.tile_addr(ppu));
+                Step::High
+            }
+            _ => {
+                self.high = ppu.read_vram(self.tile_addr(ppu) + 1);
+                Step::Push
+            }
+        };
+    }
+
+    // Merge the next object's row into the object FIFO, under any pixels already there
+    fn load_object(&mut self, ppu: &Ppu) {
+        let object = ppu.objects[self.next_object];
+        self.next_object += 1;
+
+        let height = ppu.object_height();
+        let mut row = ppu.ly.wrapping_add(16).wrapping_sub(object.y);
+        if object.attributes & OBJECT_Y_FLIP != 0 {
+            row = height - 1 - row;
+        }
+        let tile = if height == 16 { object.tile & 0xFE } else { object.tile };
+        let layer = if object.attributes & OBJECT_OBP1 != 0 { Layer::Obj1 } else { Layer::Obj0 };
+        let behind = object.attributes & OBJECT_BEHIND_BG != 0;
+
+        // Columns already past the left edge of the screen are lost
+        let skip = (self.x + 8).saturating_sub(object.x);
+        for column in skip..8 {
+            let column_x = if object.attributes & OBJECT_X_FLIP != 0 { 7 - column } else { column };
+            let colour = ppu.pixel(VRAM_START + tile as u16 * 16, column_x, row % 16);
+            let pixel = ObjectPixel { colour, layer, behind };
+            match self.objects.get_mut((column - skip) as usize) {
+                Some(existing) if existing.colour == 0 => *existing = pixel,
+                Some(_) => (),
+                None => self.objects.push_back(pixel),
+            }
+        }
+    }
+
+    // Whether the next object is due and stops pixels shifting out
+    fn object_due(&self, ppu: &Ppu) -> bool {
+        ppu.lcdc & LCDC_OBJECTS != 0
+            && self.discard == 0
+            && ppu.objects.get(self.next_object).is_some_and(|object| object.x <= self.x + 8)
+    }
+
+    // Run one dot of mode 3, returning true once the line is finished
+    pub fn dot(&mut self, ppu: &mut Ppu) -> bool {
+        if self.warm_up > 0 {
+            self.warm_up -= 1;
+            return false;
+        }
+
+        if let Some(dots) = self.object_fetch {
+            if dots > 1 {
+                self.object_fetch = Some(dots - 1);
+            }
+            else {
+                self.object_fetch = None;
+                self.load_object(ppu);
+            }
+            return false;
+        }
+
+        if self.object_due(ppu) {
+            if self.step == Step::Push {
+                self.object_fetch = Some(OBJECT_FETCH_DOTS);
+            }
+            else {
+                self.fetch(ppu);
+            }
+            return false;
+        }
+
+        if !self.window && ppu.window_enabled() && self.x + 7 >= ppu.wx {
+            self.window = true;
+            self.bg.clear();
+            self.fetch_x = 0;
+            self.step = Step::Tile;
+            self.step_dots = 0;
+            self.discard = 7u8.saturating_sub(ppu.wx);
+        }
+
+        self.fetch(ppu);
+        let Some(colour) = self.bg.pop_front() else {
+            return false;
+        };
+        if self.discard > 0 {
+            self.discard -= 1;
+            return false;
+        }
+
+        // On the DMG clearing LCDC bit 0 blanks both the background and the window
+        let colour = if ppu.lcdc & LCDC_BG != 0 { colour } else { 0 };
+        let pixel = match self.objects.pop_front() {
+            Some(object) if object.colour != 0 && ppu.lcdc & LCDC_OBJECTS != 0 && !(object.behind && colour != 0) => {
+                ppu.shade(object.colour, object.layer)
+            }
+            _ => ppu.shade(colour, Layer::Bg),
+        };
+        ppu.framebuffer[ppu.ly as usize * WIDTH + self.x as usize] = pixel;
+
+        self.x += 1;
+        if self.x as usize == WIDTH {
+            if self.window {
+                ppu.window_line += 1;
+            }
+            return true;
+        }
+        false
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::tests::{object, tiles};
+    use crate::ppu::{Mode, Renderer, BGP, LCDC, LCDC_ENABLE, LCDC_WINDOW, OAM_SCAN_DOTS, OBP1, SCX, WX, WY};
+
+    const LCDC_ALL: u8 = LCDC_ENABLE | LCDC_WINDOW_MAP | LCDC_WINDOW | LCDC_UNSIGNED_TILES | LCDC_OBJECTS | LCDC_BG;
+
+    fn fifo(lcdc: u8) -> Ppu {
+        let mut ppu = tiles(lcdc);
+        ppu.set_renderer(Renderer::Fifo);
+        ppu
+    }
+
+    fn run_until(ppu: &mut Ppu, mode: Mode) {
+        let mut requests = Default::default();
+        while ppu.mode != mode {
+            ppu.dot(&mut requests);
+        }
+    }
+
+    // Length of mode 3 on the next line
+    fn drawing_dots(ppu: &mut Ppu) -> u16 {
+        run_until(ppu, Mode::OamScan);
+        run_until(ppu, Mode::HBlank);
+        ppu.dot - OAM_SCAN_DOTS
+    }
+
+    #[test]
+    fn mode_3_length() {
+        let mut ppu = fifo(LCDC_ENABLE | LCDC_UNSIGNED_TILES | LCDC_OBJECTS | LCDC_BG);
+        assert_eq!(drawing_dots(&mut ppu), 172);
+
+        ppu.write(SCX, 3);
+        assert_eq!(drawing_dots(&mut ppu), 175);
+        ppu.write(SCX, 0);
+
+        object(&mut ppu, 0, 16, 48, 0, 0);
+        let dots = drawing_dots(&mut ppu);
+        assert!((178..=183).contains(&dots), "{}", dots);
+        object(&mut ppu, 0, 0, 0, 0, 0);
+
+        ppu.write(LCDC, LCDC_ALL);
+        ppu.write(WX, 87);
+        assert_eq!(drawing_dots(&mut ppu), 178);
+    }
+
+    #[test]
+    fn same_picture_as_scanline() {
+        let mut scanline = tiles(LCDC_ALL);
+        let mut fifo = fifo(LCDC_ALL);
+        for ppu in [&mut scanline, &mut fifo] {
+            for addr in (0x8030..0x8040).step_by(2) {
+                ppu.write_vram(addr, 0x3C);
+            }
+            for (i, tile) in (0..0x400).map(|i| (i * 7 % 5) as u8).enumerate() {
+                ppu.write_vram(0x9800 + i as u16, tile);
+            }
+            ppu.write(SCX, 13);
+            ppu.write(WY, 40);
+            ppu.write(WX, 100);
+            ppu.write(OBP1, 0x1B);
+            object(ppu, 0, 30, 5, 3, OBJECT_OBP1);
+            object(ppu, 1, 34, 60, 3, OBJECT_X_FLIP | OBJECT_BEHIND_BG);
+            object(ppu, 2, 34, 62, 1, 0);
+            run_until(ppu, Mode::VBlank);
+        }
+        assert!(scanline.framebuffer == fifo.framebuffer);
+    }
+
+    #[test]
+    fn renderer_switched_mid_line() {
+        for (from, to) in [(Renderer::Scanline, Renderer::Fifo), (Renderer::Fifo, Renderer::Scanline)] {
+            let mut ppu = tiles(LCDC_ENABLE | LCDC_UNSIGNED_TILES | LCDC_BG);
+            ppu.set_renderer(from);
+            run_until(&mut ppu, Mode::Drawing);
+            ppu.set_renderer(to);
+            run_until(&mut ppu, Mode::HBlank);
+            assert_eq!(ppu.framebuffer[..8], [3; 8]);
+        }
+    }
+
+    #[test]
+    fn mid_line_bgp_write() {
+        let mut ppu = fifo(LCDC_ENABLE | LCDC_UNSIGNED_TILES | LCDC_BG);
+        run_until(&mut ppu, Mode::Drawing);
+        // First pixel out after the discarded fetch and one real one
+        let mut requests = Default::default();
+        for _ in 0..12 + 40 {
+            ppu.dot(&mut requests);
+        }
+        ppu.write(BGP, 0xFF);
+        run_until(&mut ppu, Mode::HBlank);
+        assert_eq!(ppu.framebuffer[..8], [3; 8]);
+        assert_eq!(ppu.framebuffer[8..40], [0; 32]);
+        assert_eq!(ppu.framebuffer[40..160], [3; 120]);
+    }
+}
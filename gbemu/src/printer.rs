@@ -0,0 +1,338 @@
+// Game Boy Printer
+//
+// The Game Boy is master and sends packets of:
+//   0x88 0x33, command, compression flag, data length (LE), data, checksum (LE)
+// then two more bytes, during which the printer answers 0x81 (its device ID) and its status.
+// It answers 0x00 to everything else. The checksum is the sum of every byte from the command
+// to the end of the data.
+//   INIT clears the image buffer.
+//   DATA adds 640 bytes, two rows of 20 tiles, to the buffer, possibly RLE compressed. An empty
+//   DATA packet marks the end of the image.
+//   PRINT prints the buffer: the number of sheets, the margins, the palette and the exposure.
+//   STATUS does nothing but ask for the status.
+// Printing takes a while, during which the printer reports itself busy.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::png;
+use crate::serial::SerialDevice;
+
+const MAGIC: [u8; 2] = [0x88, 0x33];
+const DEVICE_ID: u8 = 0x81;
+
+const INIT: u8 = 0x01;
+const PRINT: u8 = 0x02;
+const DATA: u8 = 0x04;
+const STATUS: u8 = 0x0F;
+
+// Status bits
+const CHECKSUM_ERROR: u8 = 0x01;
+const PRINTING: u8 = 0x02;
+const IMAGE_DATA_FULL: u8 = 0x04;
+const UNPROCESSED_DATA: u8 = 0x08;
+const PACKET_ERROR: u8 = 0x10;
+
+const WIDTH: usize = 160;
+const BYTES_PER_TILE_ROW: usize = 20 * 16;
+const BYTES_PER_PACKET: usize = 2 * BYTES_PER_TILE_ROW;
+const BUFFER_SIZE: usize = 0x2000;
+
+// Pixel rows fed per unit of margin
+const ROWS_PER_MARGIN: usize = 8;
+
+// M-cycles the printer stays busy after a PRINT, about a quarter of a second
+const PRINT_CYCLES: u64 = 0x40000;
+
+// Paper is white, the four shades of the thermal head get darker from there
+const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum State {
+    Magic(usize),
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    DeviceId,
+    Status,
+}
+
+// A printed image, margins included, 4 bytes per pixel
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PrintedPage {
+    pub width: usize,
+    pub height: usize,
+    pub rgba: Vec<u8>,
+}
+
+impl PrintedPage {
+    pub fn png(&self) -> Vec<u8> {
+        png::encode_rgba(self.width, self.height, &self.rgba)
+    }
+
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn save_png(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        png::write(path, self.width, self.height, &self.rgba)
+    }
+}
+
+pub struct Printer {
+    state: State,
+    command: u8,
+    compressed: bool,
+    length: usize,
+    data: Vec<u8>,
+    sum: u16,
+    checksum: u16,
+    status: u8,
+    // M-cycles left until the current print is done
+    busy: u64,
+    buffer: Vec<u8>,
+    pages: Rc<RefCell<Vec<PrintedPage>>>,
+}
+
+impl Default for Printer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Printer {
+    pub fn new() -> Self {
+        Self {
+            state: State::Magic(0),
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            sum: 0,
+            checksum: 0,
+            status: 0,
+            busy: 0,
+            buffer: Vec::new(),
+            pages: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    // Shared view of the pages printed, which stays valid once the device is plugged in
+    pub fn pages(&self) -> Rc<RefCell<Vec<PrintedPage>>> {
+        Rc::clone(&self.pages)
+    }
+
+    fn receive(&mut self, byte: u8) {
+        match self.state {
+            State::Command | State::Compression | State::LengthLow | State::LengthHigh | State::Data => {
+                self.sum = self.sum.wrapping_add(byte as u16);
+            }
+            _ => {}
+        }
+
+        self.state = match self.state {
+            State::Magic(0) if byte == MAGIC[0] => State::Magic(1),
+            State::Magic(1) if byte == MAGIC[1] => {
+                self.sum = 0;
+                State::Command
+            }
+            // A repeated first magic byte can still start a packet
+            State::Magic(_) => if byte == MAGIC[0] { State::Magic(1) } else { State::Magic(0) },
+            State::Command => {
+                self.command = byte;
+                State::Compression
+            }
+            State::Compression => {
+                self.compressed = byte & 0x01 != 0;
+                State::LengthLow
+            }
+            State::LengthLow => {
+                self.length = byte as usize;
+                State::LengthHigh
+            }
+            State::LengthHigh => {
+                self.length |= (byte as usize) << 8;
+                self.data.clear();
+                if self.length == 0 { State::ChecksumLow } else { State::Data }
+            }
+            State::Data => {
+                self.data.push(byte);
+                if self.data.len() == self.length { State::ChecksumLow } else { State::Data }
+            }
+            State::ChecksumLow => {
+                self.checksum = byte as u16;
+                State::ChecksumHigh
+            }
+            State::ChecksumHigh => {
+                self.checksum |= (byte as u16) << 8;
+                State::DeviceId
+            }
+            State::DeviceId => {
+                self.process();
+                State::Status
+            }
+            State::Status => State::Magic(0),
+        };
+    }
+
+    fn process(&mut self) {
+        if self.checksum != self.sum {
+            self.status |= CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !CHECKSUM_ERROR;
+
+        match self.command {
+            INIT => {
+                self.buffer.clear();
+                self.status = 0;
+                self.busy = 0;
+            }
+            DATA if self.data.is_empty() => {}
+            DATA => {
+                let data = if self.compressed { decompress(&self.data) } else { std::mem::take(&mut self.data) };
+                let room = BUFFER_SIZE - self.buffer.len();
+                self.buffer.extend(data.into_iter().take(room));
+                self.status |= UNPROCESSED_DATA;
+                if self.buffer.len() + BYTES_PER_PACKET > BUFFER_SIZE {
+                    self.status |= IMAGE_DATA_FULL;
+                }
+            }
+            PRINT if self.data.len() == 4 => {
+                let (sheets, margins, palette) = (self.data[0], self.data[1], self.data[2]);
+                if sheets > 0 {
+                    let page = render(&self.buffer, margins >> 4, margins & 0x0F, palette);
+                    self.pages.borrow_mut().push(page);
+                }
+                self.buffer.clear();
+                self.status &= !(UNPROCESSED_DATA | IMAGE_DATA_FULL);
+                self.status |= PRINTING;
+                self.busy = PRINT_CYCLES;
+            }
+            STATUS => {}
+            _ => self.status |= PACKET_ERROR,
+        }
+    }
+}
+
+impl SerialDevice for Printer {
+    fn exchange(&mut self, outgoing: u8) -> u8 {
+        let reply = match self.state {
+            State::DeviceId => DEVICE_ID,
+            State::Status => self.status,
+            _ => 0x00,
+        };
+        self.receive(outgoing);
+        reply
+    }
+
+    fn tick(&mut self, m_cycles: u64) {
+        if self.busy > 0 {
+            self.busy = self.busy.saturating_sub(m_cycles);
+            if self.busy == 0 {
+                self.status &= !PRINTING;
+            }
+        }
+    }
+}
+
+// Runs of a byte have bit 7 set in the control byte and repeat the next byte (control & 0x7F)
+// + 2 times. Otherwise the next control + 1 bytes are copied as they are.
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(BYTES_PER_PACKET);
+    let mut bytes = data.iter();
+    while let Some(&control) = bytes.next() {
+        if control & 0x80 != 0 {
+            if let Some(&byte) = bytes.next() {
+                out.extend(std::iter::repeat_n(byte, (control & 0x7F) as usize + 2));
+            }
+        }
+        else {
+            out.extend(bytes.by_ref().take(control as usize + 1));
+        }
+    }
+    out
+}
+
+// Lay the buffer's tiles out 20 to a row, with blank rows fed before and after
+fn render(buffer: &[u8], margin_before: u8, margin_after: u8, palette: u8) -> PrintedPage {
+    let image_rows = buffer.len() / BYTES_PER_TILE_ROW * 8;
+    let before = margin_before as usize * ROWS_PER_MARGIN;
+    let height = before + image_rows + margin_after as usize * ROWS_PER_MARGIN;
+    let mut rgba = vec![0xFF; WIDTH * height * 4];
+
+    for y in 0..image_rows {
+        for x in 0..WIDTH {
+            let offset = y / 8 * BYTES_PER_TILE_ROW + x / 8 * 16 + y % 8 * 2;
+            let bit = 7 - x % 8;
+            let colour = ((buffer[offset + 1] >> bit) & 1) << 1 | ((buffer[offset] >> bit) & 1);
+            let shade = SHADES[((palette >> (colour * 2)) & 0x03) as usize];
+            let pixel = ((before + y) * WIDTH + x) * 4;
+            rgba[pixel..pixel + 3].fill(shade);
+        }
+    }
+    PrintedPage { width: WIDTH, height, rgba }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Send a packet as the Game Boy would, returning the device ID and status bytes
+    fn send(printer: &mut Printer, command: u8, compressed: bool, data: &[u8]) -> (u8, u8) {
+        let mut packet = vec![command, compressed as u8];
+        packet.extend((data.len() as u16).to_le_bytes());
+        packet.extend_from_slice(data);
+        let sum = packet.iter().fold(0u16, |sum, byte| sum.wrapping_add(*byte as u16));
+        packet.extend(sum.to_le_bytes());
+
+        for byte in MAGIC.iter().chain(&packet) {
+            assert_eq!(printer.exchange(*byte), 0x00);
+        }
+        (printer.exchange(0x00), printer.exchange(0x00))
+    }
+
+    #[test]
+    fn prints_compressed_data_with_margins() {
+        let mut printer = Printer::new();
+        let pages = printer.pages();
+        assert_eq!(send(&mut printer, INIT, false, &[]), (DEVICE_ID, 0x00));
+
+        // Two tile rows of colour 1 then colour 3, 320 bytes each of 0xFF/0x00 and 0xFF/0xFF
+        let mut data = Vec::new();
+        for _ in 0..160 {
+            data.extend([0x01, 0xFF, 0x00]);
+        }
+        data.extend([0xFF, 0xFF, 0xFF, 0xFF, 0xBC, 0xFF]);
+        assert_eq!(decompress(&data).len(), BYTES_PER_PACKET);
+        assert_eq!(send(&mut printer, DATA, true, &data), (DEVICE_ID, UNPROCESSED_DATA));
+        send(&mut printer, DATA, false, &[]);
+
+        // One sheet, a margin of one before and two after, colours 1 and 3 light and black
+        let (_, status) = send(&mut printer, PRINT, false, &[0x01, 0x12, 0b11_00_01_00, 0x40]);
+        assert_eq!(status, PRINTING);
+        printer.tick(PRINT_CYCLES);
+        assert_eq!(send(&mut printer, STATUS, false, &[]), (DEVICE_ID, 0x00));
+
+        let pages = pages.borrow();
+        assert_eq!(pages.len(), 1);
+        let page = &pages[0];
+        assert_eq!((page.width, page.height), (160, 8 + 16 + 16));
+        let pixel = |x: usize, y: usize| &page.rgba[(y * WIDTH + x) * 4..(y * WIDTH + x + 1) * 4];
+        assert_eq!(pixel(0, 0), [0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(pixel(5, 8), [0xAA, 0xAA, 0xAA, 0xFF]);
+        assert_eq!(pixel(159, 23), [0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(pixel(0, 39), [0xFF, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn bad_checksum() {
+        let mut printer = Printer::new();
+        for byte in [0x88, 0x33, INIT, 0x00, 0x00, 0x00, 0x02, 0x00] {
+            printer.exchange(byte);
+        }
+        assert_eq!((printer.exchange(0), printer.exchange(0)), (DEVICE_ID, CHECKSUM_ERROR));
+    }
+}
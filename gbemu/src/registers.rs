@@ -1,3 +1,5 @@
+use crate::cartridge::Header;
+use crate::model::Model;
 use crate::word::Word;
 
 // CPU registers, 4 two byte words consisting of two 8bit registers, little endian
@@ -31,15 +33,40 @@ impl Registers {
     const HALF_CARRY_FLAG_MASK: u8 = 0b0010_0000;
     const CARRY_FLAG_MASK: u8 = 0b0001_0000;
 
-    pub fn new() -> Self {
-        Self {
-            af: Word(0x01, 0xb0),
-            bc: Word(0x00, 0x13),
-            de: Word(0x00, 0xd8),
-            hl: Word(0x01, 0x4d),
+    // State the model's boot ROM hands over to the cartridge in
+    pub fn post_boot(model: Model, header: &Header) -> Self {
+        let state = model.post_boot();
+        let word = |value: u16| Word((value >> 8) as u8, value as u8);
+        let mut registers = Self {
+            af: Word(state.a, state.f.unwrap_or(0)),
+            bc: word(state.bc),
+            de: word(state.de),
+            hl: word(state.hl),
 
             pc: 0x0100,
             sp: 0xfffe,
+        };
+
+        if state.f.is_none() {
+            // The flags of the boot ROM's final ADD of the checksum
+            let (sum, checksum) = (header.header_sum, header.header_checksum);
+            let (result, carry) = sum.overflowing_add(checksum);
+            let half_carry = (sum & 0x0F) + (checksum & 0x0F) > 0x0F;
+            registers.set_flags(result == 0, false, half_carry, carry);
+        }
+        registers
+    }
+
+    // State at power on, before the boot ROM has run
+    pub fn power_on() -> Self {
+        Self {
+            af: Word(0x00, 0x00),
+            bc: Word(0x00, 0x00),
+            de: Word(0x00, 0x00),
+            hl: Word(0x00, 0x00),
+
+            pc: 0x0000,
+            sp: 0x0000,
         }
     }
 
@@ -69,12 +96,13 @@ impl Registers {
         }
     }
 
+    // (lsb, msb)
     pub fn get8_8(&self, reg: Register16) -> (u8, u8) {
         match reg {
-            Register16::AF => (self.af.0, self.af.1),
-            Register16::BC => (self.bc.0, self.bc.1),
-            Register16::DE => (self.de.0, self.de.1),
-            Register16::HL => (self.hl.0, self.hl.1),
+            Register16::AF => (self.af.1, self.af.0),
+            Register16::BC => (self.bc.1, self.bc.0),
+            Register16::DE => (self.de.1, self.de.0),
+            Register16::HL => (self.hl.1, self.hl.0),
             Register16::PC => ((self.pc & 255) as u8, (self.pc >> 8) as u8),
             Register16::SP => ((self.sp & 255) as u8, (self.sp >> 8) as u8),
         }
@@ -0,0 +1,222 @@
+// SB/SC and the link port
+//
+// With the internal clock the Game Boy is master and shifts a bit out (and one in) every
+// 128 M-cycles, or every 4 on the CGB with the fast clock selected. With the external clock it
+// waits for whatever is plugged in to drive the transfer. Either way the serial interrupt is
+// requested when the byte is done.
+//
+// Transfers are exchanged a whole byte at a time, at the end of the eighth bit.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub const SB: u16 = 0xFF01;
+pub const SC: u16 = 0xFF02;
+
+const SC_START: u8 = 0x80;
+const SC_FAST: u8 = 0x02;
+const SC_INTERNAL: u8 = 0x01;
+
+const CYCLES_PER_BIT: u64 = 128;
+const FAST_CYCLES_PER_BIT: u64 = 4;
+
+// Whatever is plugged into the link port
+pub trait SerialDevice {
+    // The Game Boy has clocked out a byte as master, returns the byte shifted back in
+    fn exchange(&mut self, outgoing: u8) -> u8;
+
+    // Called every M-cycle while the Game Boy waits on the external clock with outgoing in SB.
+    // Returns the byte shifted in once the device has clocked a transfer.
+    fn external_clock(&mut self, _outgoing: u8) -> Option<u8> {
+        None
+    }
+
+    // Advance the device, called every M-cycle whether or not a transfer is in progress
+    fn tick(&mut self, _m_cycles: u64) {}
+}
+
+// Nothing plugged in, the line floats high
+pub struct Disconnected;
+
+impl SerialDevice for Disconnected {
+    fn exchange(&mut self, _outgoing: u8) -> u8 {
+        0xFF
+    }
+}
+
+// Records every byte sent, which is how test ROMs such as blargg's report their results
+#[derive(Default)]
+pub struct Capture {
+    received: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Capture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Shared view of the bytes received, which stays valid once the device is plugged in
+    pub fn received(&self) -> Rc<RefCell<Vec<u8>>> {
+        Rc::clone(&self.received)
+    }
+}
+
+impl SerialDevice for Capture {
+    fn exchange(&mut self, outgoing: u8) -> u8 {
+        self.received.borrow_mut().push(outgoing);
+        0xFF
+    }
+}
+
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    cgb: bool,
+    // M-cycles until an internally clocked transfer completes
+    remaining: u64,
+    device: Box<dyn SerialDevice>,
+}
+
+impl Serial {
+    pub fn new(cgb: bool) -> Self {
+        Self { sb: 0, sc: 0, cgb, remaining: 0, device: Box::new(Disconnected) }
+    }
+
+    pub fn connect(&mut self, device: Box<dyn SerialDevice>) {
+        self.device = device;
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            SB => self.sb,
+            _ if self.cgb => self.sc | 0x7C,
+            _ => self.sc | 0x7E,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            SB => self.sb = value,
+            _ => {
+                let mask = if self.cgb { SC_START | SC_FAST | SC_INTERNAL } else { SC_START | SC_INTERNAL };
+                self.sc = value & mask;
+                if self.sc & (SC_START | SC_INTERNAL) == SC_START | SC_INTERNAL {
+                    let per_bit = if self.sc & SC_FAST != 0 { FAST_CYCLES_PER_BIT } else { CYCLES_PER_BIT };
+                    self.remaining = 8 * per_bit;
+                }
+            }
+        }
+    }
+
+    // Set a register as the boot ROM left it
+    pub fn restore(&mut self, addr: u16, value: u8) {
+        match addr {
+            SB => self.sb = value,
+            _ => self.sc = value & (SC_START | SC_FAST | SC_INTERNAL),
+        }
+    }
+
+    fn complete(&mut self, incoming: u8) -> bool {
+        self.sb = incoming;
+        self.sc &= !SC_START;
+        true
+    }
+
+    // Advance one M-cycle, returning true if the serial interrupt should be requested
+    pub fn step(&mut self) -> bool {
+        self.device.tick(1);
+        if self.sc & SC_START == 0 {
+            return false;
+        }
+
+        if self.sc & SC_INTERNAL != 0 {
+            self.remaining -= 1;
+            if self.remaining == 0 {
+                let incoming = self.device.exchange(self.sb);
+                return self.complete(incoming);
+            }
+            false
+        }
+        else {
+            self.clocked_externally()
+        }
+    }
+
+    // Advance one M-cycle with the CPU stopped. The internal clock is stopped too, but the
+    // device keeps running and can still clock a byte in.
+    pub fn step_stopped(&mut self) -> bool {
+        self.device.tick(1);
+        if self.sc & (SC_START | SC_INTERNAL) != SC_START {
+            return false;
+        }
+        self.clocked_externally()
+    }
+
+    fn clocked_externally(&mut self) -> bool {
+        match self.device.external_clock(self.sb) {
+            Some(incoming) => self.complete(incoming),
+            None => false,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn internal_clock_transfer() {
+        let capture = Capture::new();
+        let received = capture.received();
+        let mut serial = Serial::new(false);
+        serial.connect(Box::new(capture));
+
+        serial.write(SB, b'A');
+        serial.write(SC, SC_START | SC_INTERNAL);
+        assert_eq!((1..8 * CYCLES_PER_BIT).filter(|_| serial.step()).count(), 0);
+        assert!(serial.step());
+        assert_eq!(*received.borrow(), b"A");
+        assert_eq!(serial.read(SB), 0xFF);
+        assert_eq!(serial.read(SC), 0x7F);
+    }
+
+    #[test]
+    fn cgb_fast_clock() {
+        let mut serial = Serial::new(true);
+        serial.write(SC, SC_START | SC_FAST | SC_INTERNAL);
+        assert_eq!((0..8 * FAST_CYCLES_PER_BIT).filter(|_| serial.step()).count(), 1);
+
+        // No fast clock on the DMG
+        let mut serial = Serial::new(false);
+        serial.write(SC, SC_START | SC_FAST | SC_INTERNAL);
+        assert_eq!((0..8 * FAST_CYCLES_PER_BIT).filter(|_| serial.step()).count(), 0);
+    }
+
+    #[test]
+    fn external_clock_waits_for_the_device() {
+        struct Partner;
+        impl SerialDevice for Partner {
+            fn exchange(&mut self, _outgoing: u8) -> u8 {
+                0xFF
+            }
+
+            fn external_clock(&mut self, outgoing: u8) -> Option<u8> {
+                Some(outgoing + 1)
+            }
+        }
+
+        // Nothing plugged in never clocks it
+        let mut serial = Serial::new(false);
+        serial.write(SB, 0x41);
+        serial.write(SC, SC_START);
+        for _ in 0..1000 {
+            assert!(!serial.step());
+        }
+
+        serial.connect(Box::new(Partner));
+        assert!(serial.step());
+        assert_eq!(serial.read(SB), 0x42);
+        assert_eq!(serial.read(SC) & SC_START, 0);
+    }
+}
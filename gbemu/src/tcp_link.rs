@@ -0,0 +1,303 @@
+// A link cable tunnelled over TCP, for linking emulators in different processes or on
+// different machines
+//
+// Each side sends fixed size frames: a tag, the sender's M-cycle count, and a data byte.
+//   Transfer: the sender clocked a byte out as master, and waits for the Reply. The receiver
+//     takes it at the sender's M-cycle count, or as soon as it can if already past that.
+//   Reply: the byte shifted back, SB if armed on the external clock or 0xFF if not
+//   Sync: sent periodically so each side knows how far along the other is
+// A side more than max_skew M-cycles ahead of the other waits for it to catch up, so the
+// master can't race past a slave that's still getting ready, however slow the connection.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use crate::log as console_log;
+use crate::serial::SerialDevice;
+
+const TRANSFER: u8 = 0x01;
+const REPLY: u8 = 0x02;
+const SYNC: u8 = 0x03;
+
+const FRAME_SIZE: usize = 10;
+
+// M-cycles between checks for incoming frames, and between syncs
+const POLL_INTERVAL: u64 = 32;
+const SYNC_INTERVAL: u64 = 128;
+const DEFAULT_MAX_SKEW: u64 = 512;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Message {
+    Transfer(u8),
+    Reply(u8),
+    Sync,
+}
+
+pub struct TcpLink {
+    stream: TcpStream,
+    connected: bool,
+    blocking: bool,
+    buffer: Vec<u8>,
+    cycle: u64,
+    peer_cycle: u64,
+    max_skew: u64,
+    // SB while armed on the external clock, as of the last M-cycle
+    armed: Option<u8>,
+    // Byte a peer master clocked in, for the next external_clock to return
+    delivered: Option<u8>,
+    // A Transfer from a peer ahead of us, held until we reach its M-cycle count
+    pending: Option<(u64, u8)>,
+}
+
+impl TcpLink {
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Self::new(TcpStream::connect(addr)?)
+    }
+
+    // Wait for the other side to connect
+    pub fn accept(listener: &TcpListener) -> io::Result<Self> {
+        let (stream, peer) = listener.accept()?;
+        console_log(format!("Link cable connected to {peer}").as_str());
+        Self::new(stream)
+    }
+
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        Ok(Self {
+            stream,
+            connected: true,
+            blocking: false,
+            buffer: Vec::new(),
+            cycle: 0,
+            peer_cycle: 0,
+            max_skew: DEFAULT_MAX_SKEW,
+            armed: None,
+            delivered: None,
+            pending: None,
+        })
+    }
+
+    // How many M-cycles either side may get ahead of the other
+    pub fn with_max_skew(mut self, m_cycles: u64) -> Self {
+        self.max_skew = m_cycles;
+        self
+    }
+
+    fn disconnect(&mut self, error: &str) {
+        console_log(format!("Link cable disconnected: {error}").as_str());
+        self.connected = false;
+    }
+
+    fn send(&mut self, message: Message) {
+        if !self.connected {
+            return;
+        }
+        let (tag, data) = match message {
+            Message::Transfer(byte) => (TRANSFER, byte),
+            Message::Reply(byte) => (REPLY, byte),
+            Message::Sync => (SYNC, 0),
+        };
+        let mut frame = [0u8; FRAME_SIZE];
+        frame[0] = tag;
+        frame[1..9].copy_from_slice(&self.cycle.to_le_bytes());
+        frame[9] = data;
+
+        if let Err(error) = self.set_blocking(true).and_then(|_| self.stream.write_all(&frame)) {
+            self.disconnect(&error.to_string());
+        }
+    }
+
+    fn set_blocking(&mut self, blocking: bool) -> io::Result<()> {
+        if self.blocking != blocking {
+            self.stream.set_nonblocking(!blocking)?;
+            self.blocking = blocking;
+        }
+        Ok(())
+    }
+
+    // Read what's available, or wait for something if blocking. False if nothing was read.
+    fn fill(&mut self, block: bool) -> bool {
+        if !self.connected {
+            return false;
+        }
+        let mut chunk = [0u8; 256];
+        let read = self.set_blocking(block).and_then(|_| self.stream.read(&mut chunk));
+        match read {
+            Ok(0) => {
+                self.disconnect("closed by peer");
+                false
+            }
+            Ok(n) => {
+                self.buffer.extend_from_slice(&chunk[..n]);
+                true
+            }
+            Err(error) if error.kind() == ErrorKind::WouldBlock => false,
+            Err(error) => {
+                self.disconnect(&error.to_string());
+                false
+            }
+        }
+    }
+
+    // A message and the sender's M-cycle count when it was sent
+    fn receive(&mut self, block: bool) -> Option<(u64, Message)> {
+        while self.buffer.len() < FRAME_SIZE {
+            if !self.fill(block) {
+                return None;
+            }
+        }
+
+        let frame: Vec<u8> = self.buffer.drain(..FRAME_SIZE).collect();
+        let mut cycle = [0u8; 8];
+        cycle.copy_from_slice(&frame[1..9]);
+        let cycle = u64::from_le_bytes(cycle);
+        self.peer_cycle = self.peer_cycle.max(cycle);
+
+        let message = match frame[0] {
+            TRANSFER => Message::Transfer(frame[9]),
+            REPLY => Message::Reply(frame[9]),
+            SYNC => Message::Sync,
+            other => {
+                self.disconnect(format!("unknown frame {other:#04x}").as_str());
+                return None;
+            }
+        };
+        Some((cycle, message))
+    }
+
+    // Deal with anything but a Reply, returning the Reply if that's what it was
+    fn handle(&mut self, cycle: u64, message: Message) -> Option<u8> {
+        match message {
+            Message::Transfer(incoming) if cycle > self.cycle => {
+                self.pending = Some((cycle, incoming));
+                None
+            }
+            Message::Transfer(incoming) => {
+                self.take_transfer(incoming);
+                None
+            }
+            Message::Reply(byte) => Some(byte),
+            Message::Sync => None,
+        }
+    }
+
+    // Shift the byte in if armed, and answer with SB (or 0xFF if not) to release the peer
+    fn take_transfer(&mut self, incoming: u8) {
+        let outgoing = match self.armed {
+            Some(outgoing) => {
+                self.delivered = Some(incoming);
+                outgoing
+            }
+            None => 0xFF,
+        };
+        self.send(Message::Reply(outgoing));
+    }
+}
+
+impl SerialDevice for TcpLink {
+    fn exchange(&mut self, outgoing: u8) -> u8 {
+        self.send(Message::Transfer(outgoing));
+        while let Some((cycle, message)) = self.receive(true) {
+            if let Some(incoming) = self.handle(cycle, message) {
+                return incoming;
+            }
+        }
+        0xFF
+    }
+
+    fn external_clock(&mut self, outgoing: u8) -> Option<u8> {
+        self.armed = Some(outgoing);
+        self.delivered.take()
+    }
+
+    fn tick(&mut self, m_cycles: u64) {
+        self.cycle += m_cycles;
+        if self.cycle.is_multiple_of(POLL_INTERVAL) {
+            while let Some((cycle, message)) = self.receive(false) {
+                self.handle(cycle, message);
+            }
+        }
+        if let Some((cycle, incoming)) = self.pending {
+            if self.cycle >= cycle {
+                self.pending = None;
+                self.take_transfer(incoming);
+            }
+        }
+
+        if self.cycle.is_multiple_of(SYNC_INTERVAL) {
+            self.send(Message::Sync);
+            while self.connected && self.cycle > self.peer_cycle + self.max_skew {
+                if let Some((cycle, message)) = self.receive(true) {
+                    self.handle(cycle, message);
+                }
+            }
+        }
+
+        // Re-armed by external_clock if SC still says so
+        self.armed = None;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    use crate::emulator::Emulator;
+    use crate::link::tests::exchange_rom;
+    use crate::model::Model;
+
+    fn run_until_halted(emulator: &mut Emulator) -> u8 {
+        while !emulator.cpu().halted && emulator.cycles() < 100_000 {
+            emulator.step();
+        }
+        emulator.cpu().registers.af.0
+    }
+
+    #[test]
+    fn localhost_exchange() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let slave = thread::spawn(move || {
+            let mut emulator = Emulator::new(exchange_rom(0x34, 0x80), Model::Dmg).unwrap();
+            emulator.connect_serial(Box::new(TcpLink::accept(&listener).unwrap()));
+            run_until_halted(&mut emulator)
+        });
+
+        let mut emulator = Emulator::new(exchange_rom(0x12, 0x81), Model::Dmg).unwrap();
+        emulator.connect_serial(Box::new(TcpLink::connect(addr).unwrap()));
+        let received = run_until_halted(&mut emulator);
+        // Hang up, so the slave can't be left waiting on us
+        drop(emulator);
+        assert_eq!(received, 0x34);
+        assert_eq!(slave.join().unwrap(), 0x12);
+    }
+
+    #[test]
+    fn slave_answers_while_stopped() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Arm on the external clock and STOP, never to be woken
+        let mut rom = exchange_rom(0x34, 0x80);
+        rom[0x0108..0x010A].copy_from_slice(&[0x10, 0x00]);
+        let slave = thread::spawn(move || {
+            let mut emulator = Emulator::new(rom, Model::Dmg).unwrap();
+            emulator.connect_serial(Box::new(TcpLink::accept(&listener).unwrap()));
+            while emulator.cycles() < 20_000 {
+                emulator.step();
+            }
+            emulator.cpu().stopped
+        });
+
+        let mut emulator = Emulator::new(exchange_rom(0x12, 0x81), Model::Dmg).unwrap();
+        emulator.connect_serial(Box::new(TcpLink::connect(addr).unwrap()));
+        let received = run_until_halted(&mut emulator);
+        drop(emulator);
+        assert_eq!(received, 0x34);
+        assert!(slave.join().unwrap());
+    }
+}
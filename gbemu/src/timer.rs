@@ -0,0 +1,176 @@
+// DIV, TIMA, TMA and TAC
+//
+// DIV is the top byte of a 16-bit counter running at the T-cycle rate. TIMA counts falling
+// edges of the counter bit TAC selects (ANDed with the enable bit), so resetting DIV or
+// changing TAC can bump it too, as on hardware.
+
+pub const DIV: u16 = 0xFF04;
+pub const TIMA: u16 = 0xFF05;
+pub const TMA: u16 = 0xFF06;
+pub const TAC: u16 = 0xFF07;
+
+const TAC_ENABLE: u8 = 0b100;
+
+pub struct Timer {
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    // TIMA overflowed last M-cycle and reads 0 until TMA is reloaded this one
+    overflow: bool,
+    // TMA was reloaded this M-cycle, during which TIMA writes are ignored and TMA writes pass
+    // straight through to TIMA
+    reloading: bool,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Self { counter: 0, tima: 0, tma: 0, tac: 0, overflow: false, reloading: false }
+    }
+
+    // Counter bit TIMA counts falling edges of, for each TAC clock select
+    fn selected_bit(&self) -> u16 {
+        match self.tac & 0b11 {
+            0b00 => 1 << 9,
+            0b01 => 1 << 3,
+            0b10 => 1 << 5,
+            _ => 1 << 7,
+        }
+    }
+
+    fn signal(&self) -> bool {
+        self.tac & TAC_ENABLE != 0 && self.counter & self.selected_bit() != 0
+    }
+
+    fn increment(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        self.overflow |= overflow;
+    }
+
+    // Advance one M-cycle, returning true if the timer interrupt should be requested
+    pub fn step(&mut self) -> bool {
+        self.reloading = false;
+        let mut interrupt = false;
+        if self.overflow {
+            self.overflow = false;
+            self.tima = self.tma;
+            self.reloading = true;
+            interrupt = true;
+        }
+
+        let before = self.signal();
+        self.counter = self.counter.wrapping_add(4);
+        if before && !self.signal() {
+            self.increment();
+        }
+        interrupt
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            DIV => (self.counter >> 8) as u8,
+            TIMA => self.tima,
+            TMA => self.tma,
+            _ => self.tac | 0xF8,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        let before = self.signal();
+        match addr {
+            DIV => self.counter = 0,
+            TIMA => {
+                if !self.reloading {
+                    // Cancels a pending reload, and its interrupt
+                    self.tima = value;
+                    self.overflow = false;
+                }
+            }
+            TMA => {
+                self.tma = value;
+                if self.reloading {
+                    self.tima = value;
+                }
+            }
+            _ => self.tac = value & 0x07,
+        }
+
+        if before && !self.signal() {
+            self.increment();
+        }
+    }
+
+    // Set a register as the boot ROM left it, without any of the side effects of writing it
+    pub fn restore(&mut self, addr: u16, value: u8) {
+        match addr {
+            DIV => self.counter = (value as u16) << 8,
+            TIMA => self.tima = value,
+            TMA => self.tma = value,
+            _ => self.tac = value & 0x07,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(timer: &mut Timer, m_cycles: u32) -> u32 {
+        (0..m_cycles).filter(|_| timer.step()).count() as u32
+    }
+
+    #[test]
+    fn div_and_tima_count() {
+        let mut timer = Timer::new();
+        run(&mut timer, 64);
+        assert_eq!(timer.read(DIV), 1);
+
+        // 16 T-cycles per increment
+        timer.write(TAC, TAC_ENABLE | 0b01);
+        run(&mut timer, 40);
+        assert_eq!(timer.read(TIMA), 10);
+    }
+
+    #[test]
+    fn overflow_reloads_after_a_cycle() {
+        let mut timer = Timer::new();
+        timer.write(TMA, 0x80);
+        timer.write(TIMA, 0xFF);
+        timer.write(TAC, TAC_ENABLE | 0b01);
+        run(&mut timer, 4);
+        assert_eq!(timer.read(TIMA), 0x00);
+        assert!(timer.step());
+        assert_eq!(timer.read(TIMA), 0x80);
+
+        // Writes in the reload cycle are ignored
+        timer.write(TIMA, 0x10);
+        assert_eq!(timer.read(TIMA), 0x80);
+
+        // Writing TIMA in the delay cancels the reload and the interrupt
+        run(&mut timer, 1);
+        timer.write(TIMA, 0xFF);
+        run(&mut timer, 2);
+        assert_eq!(timer.read(TIMA), 0x00);
+        timer.write(TIMA, 0x42);
+        assert_eq!(run(&mut timer, 1), 0);
+        assert_eq!(timer.read(TIMA), 0x42);
+    }
+
+    #[test]
+    fn div_reset_and_tac_change_can_increment() {
+        let mut timer = Timer::new();
+        timer.write(TAC, TAC_ENABLE | 0b01);
+        run(&mut timer, 2);
+        assert_eq!(timer.read(TIMA), 0);
+        // Bit 3 is set, so clearing the counter is a falling edge
+        timer.write(DIV, 0x12);
+        assert_eq!(timer.read(TIMA), 1);
+        assert_eq!(timer.read(DIV), 0);
+
+        run(&mut timer, 2);
+        timer.write(TAC, 0);
+        assert_eq!(timer.read(TIMA), 2);
+    }
+}
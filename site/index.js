@@ -1,4 +1,4 @@
-import {default as init_wasm, run} from "./gbemu/gbemu.js";
+import {default as init_wasm, Emulator, Model, Button, ButtonState} from "./gbemu/gbemu.js";
 
 let emu_wasm = null;
 
@@ -12,4 +12,70 @@ function set_wasm(w) {
     emu_wasm = w;
 }
 
-export { init, run };
\ No newline at end of file
+// Keys and the ButtonState field each one holds
+const KEYS = {
+    ArrowRight: "right",
+    ArrowLeft: "left",
+    ArrowUp: "up",
+    ArrowDown: "down",
+    KeyX: "a",
+    KeyZ: "b",
+    ShiftRight: "select",
+    Backspace: "select",
+    Enter: "start",
+};
+
+// Track the keys held, as the set of ButtonState fields they hold, for update_buttons
+function bind_keyboard() {
+    const held = new Set();
+    const track = (event, pressed) => {
+        const button = KEYS[event.code];
+        if (button === undefined) {
+            return;
+        }
+        event.preventDefault();
+        if (pressed) {
+            held.add(button);
+        }
+        else {
+            held.delete(button);
+        }
+    };
+    window.addEventListener("keydown", (event) => track(event, true));
+    window.addEventListener("keyup", (event) => track(event, false));
+    return held;
+}
+
+// Hold the buttons held on the keyboard or on the first connected gamepad (standard mapping).
+// The Gamepad API has no events for buttons, so call this once per frame. Either works on its
+// own, and neither releases what the other holds.
+function update_buttons(emulator, keyboard) {
+    const state = new ButtonState();
+    for (const button of keyboard) {
+        state[button] = true;
+    }
+
+    const gamepad = Array.from(navigator.getGamepads()).find((pad) => pad);
+    if (gamepad) {
+        const held = (i) => gamepad.buttons[i] && gamepad.buttons[i].pressed;
+        const [x, y] = gamepad.axes;
+        state.right ||= held(15) || x > 0.5;
+        state.left ||= held(14) || x < -0.5;
+        state.up ||= held(12) || y < -0.5;
+        state.down ||= held(13) || y > 0.5;
+        state.a ||= held(0);
+        state.b ||= held(1);
+        state.select ||= held(8);
+        state.start ||= held(9);
+    }
+    emulator.set_buttons(state);
+}
+
+// An ImageData straight over the emulator's RGBA framebuffer in wasm memory, without copying.
+// Make a new one every frame, as growing wasm memory leaves old views empty.
+function framebuffer_image(emulator) {
+    const pixels = new Uint8ClampedArray(emu_wasm.memory.buffer, emulator.framebuffer_ptr(), emulator.framebuffer_len());
+    return new ImageData(pixels, 160, 144);
+}
+
+export { init, Emulator, Model, Button, ButtonState, bind_keyboard, update_buttons, framebuffer_image };